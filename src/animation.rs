@@ -0,0 +1,171 @@
+use crate::math3d::{Rotation, Transform, Vec3};
+
+/// Values that can be interpolated between keyframes.
+pub trait Interpolate: Clone {
+    /// Returns the value at fraction `t` (between 0 and 1) of the way from `self` to `other`.
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+
+    /// Returns the weighted sum of four values, for cubic interpolation. The weights sum to one
+    /// but may be negative.
+    fn blend(values: [&Self; 4], weights: [f64; 4]) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+
+    fn blend(values: [&Self; 4], weights: [f64; 4]) -> Self {
+        values.iter().zip(weights).map(|(v, w)| *v * w).sum()
+    }
+}
+
+impl Interpolate for Vec3 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        self * (1.0 - t) + other * t
+    }
+
+    fn blend(values: [&Self; 4], weights: [f64; 4]) -> Self {
+        values
+            .iter()
+            .zip(weights)
+            .fold(Vec3::new(0.0, 0.0, 0.0), |sum, (v, w)| sum + *v * w)
+    }
+}
+
+impl Interpolate for Transform {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+
+    fn blend(values: [&Self; 4], weights: [f64; 4]) -> Self {
+        Transform {
+            translation: Vec3::blend(values.map(|v| &v.translation), weights),
+            rotation: Rotation::blend(values.map(|v| &v.rotation), weights),
+            scale: f64::blend(values.map(|v| &v.scale), weights),
+        }
+    }
+}
+
+/// How values between two keyframes are computed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Straight from one keyframe to the next, changing direction abruptly at the keyframes
+    Linear,
+    /// A smooth curve through all keyframes, whose direction at each keyframe is given by the
+    /// neighboring keyframes
+    CatmullRom,
+    /// Cubic Bézier curves between the keyframes, shaped by two control points per keyframe
+    Bezier,
+}
+
+/// A value that changes over time, given by its values at a number of points in time. Before
+/// the first and after the last keyframe, the value is constant.
+#[derive(Debug, Clone)]
+pub struct Keyframes<T> {
+    /// Sorted by time
+    keys: Vec<(f64, T)>,
+    /// The incoming and outgoing control points of each key, only for Bézier interpolation
+    handles: Vec<(T, T)>,
+    interpolation: Interpolation,
+}
+
+impl<T: Interpolate> Keyframes<T> {
+    /// A value that does not change over time
+    pub fn constant(value: T) -> Keyframes<T> {
+        Keyframes::new(vec![(0.0, value)])
+    }
+
+    /// Creates linearly interpolated keyframes from (time, value) pairs. Panics if `keys` is
+    /// empty.
+    pub fn new(keys: Vec<(f64, T)>) -> Keyframes<T> {
+        Keyframes::with_interpolation(keys, Interpolation::Linear)
+    }
+
+    /// Moves linearly from `start` at time 0 to `end` at time 1.
+    pub fn linear(start: T, end: T) -> Keyframes<T> {
+        Keyframes::new(vec![(0.0, start), (1.0, end)])
+    }
+
+    /// Creates keyframes from (time, value) pairs that are interpolated with a Catmull-Rom
+    /// spline. Panics if `keys` is empty.
+    pub fn catmull_rom(keys: Vec<(f64, T)>) -> Keyframes<T> {
+        Keyframes::with_interpolation(keys, Interpolation::CatmullRom)
+    }
+
+    /// Creates keyframes from (time, incoming control point, value, outgoing control point)
+    /// tuples. Between two keys, the value follows the cubic Bézier curve from the first value
+    /// over its outgoing control point and the incoming control point of the second key to the
+    /// second value. Panics if `keys` is empty.
+    pub fn bezier(mut keys: Vec<(f64, T, T, T)>) -> Keyframes<T> {
+        assert!(!keys.is_empty(), "At least one keyframe is required");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let (keys, handles) = keys
+            .into_iter()
+            .map(|(time, handle_in, value, handle_out)| ((time, value), (handle_in, handle_out)))
+            .unzip();
+        Keyframes {
+            keys,
+            handles,
+            interpolation: Interpolation::Bezier,
+        }
+    }
+
+    fn with_interpolation(mut keys: Vec<(f64, T)>, interpolation: Interpolation) -> Keyframes<T> {
+        assert!(!keys.is_empty(), "At least one keyframe is required");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Keyframes {
+            keys,
+            handles: Vec::new(),
+            interpolation,
+        }
+    }
+
+    /// Whether the value is the same at all times
+    pub fn is_constant(&self) -> bool {
+        self.keys.len() == 1
+    }
+
+    pub fn at(&self, time: f64) -> T {
+        // Index of the first keyframe after `time`
+        let next = self.keys.partition_point(|(t, _)| *t <= time);
+        if next == 0 {
+            return self.keys[0].1.clone();
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1.clone();
+        }
+
+        let (t0, v1) = &self.keys[next - 1];
+        let (t1, v2) = &self.keys[next];
+        let t = (time - t0) / (t1 - t0);
+        match self.interpolation {
+            Interpolation::Linear => v1.lerp(v2, t),
+            Interpolation::CatmullRom => {
+                // The outermost keys are repeated to get the neighbors of the first and the
+                // last segment
+                let v0 = &self.keys[next.saturating_sub(2)].1;
+                let v3 = &self.keys[(next + 1).min(self.keys.len() - 1)].1;
+                let (t2, t3) = (t * t, t * t * t);
+                let weights = [
+                    0.5 * (-t + 2.0 * t2 - t3),
+                    0.5 * (2.0 - 5.0 * t2 + 3.0 * t3),
+                    0.5 * (t + 4.0 * t2 - 3.0 * t3),
+                    0.5 * (-t2 + t3),
+                ];
+                T::blend([v0, v1, v2, v3], weights)
+            }
+            Interpolation::Bezier => {
+                let c1 = &self.handles[next - 1].1;
+                let c2 = &self.handles[next].0;
+                let s = 1.0 - t;
+                let weights = [s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t];
+                T::blend([v1, c1, c2, v2], weights)
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use crate::math3d::Vec3;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::scene_objects::{HitRecord, Material};
+use crate::tracer::{self, AmbientOcclusion};
+
+/// An arbitrary output variable: a per-pixel quantity of the surface seen by the camera that is
+/// rendered alongside the color, e.g. for compositing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance from the camera to the hit
+    Depth,
+    /// Surface normal in world space
+    Normal,
+    /// Surface color, without any lighting
+    Albedo,
+    /// Index of the object in the scene plus one
+    ObjectId,
+    /// Objects with the same material parameters share a material ID. IDs start at one, materials
+    /// that their object does not list in `Object3D::materials()` have the ID zero.
+    MaterialId,
+    /// Hit position in world space
+    Position,
+    /// Ambient occlusion of the hit, see `RenderSettings::ambient_occlusion`
+    AmbientOcclusion,
+}
+
+impl Aov {
+    /// All AOVs, in the order of their discriminants
+    pub const ALL: [Aov; 7] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Position,
+        Aov::AmbientOcclusion,
+    ];
+
+    /// The name used on the command line and in file names.
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object-id",
+            Aov::MaterialId => "material-id",
+            Aov::Position => "position",
+            Aov::AmbientOcclusion => "ao",
+        }
+    }
+
+    /// Whether the samples of a pixel are averaged. IDs cannot be averaged meaningfully, so
+    /// for them only the first sample of each pixel is kept.
+    pub fn is_averaged(self) -> bool {
+        !matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+impl std::str::FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .into_iter()
+            .find(|aov| aov.name() == s)
+            .ok_or_else(|| format!("Unknown AOV '{s}'"))
+    }
+}
+
+/// Computes the AOVs of camera rays for one scene.
+pub struct AovTracer<'a> {
+    scene: &'a Scene,
+    ambient_occlusion: AmbientOcclusion,
+    /// Material IDs by the address of the material
+    material_ids: HashMap<usize, u32>,
+}
+
+impl<'a> AovTracer<'a> {
+    pub fn new(scene: &'a Scene, ambient_occlusion: Option<&AmbientOcclusion>) -> AovTracer<'a> {
+        // Materials are compared by their parameters, which are all floats
+        let mut ids_by_params: HashMap<[u64; 7], u32> = HashMap::new();
+        let mut material_ids = HashMap::new();
+        for material in scene.objects.iter().flat_map(|obj| obj.materials()) {
+            let next_id = ids_by_params.len() as u32 + 1;
+            let id = *ids_by_params
+                .entry(material_params(material))
+                .or_insert(next_id);
+            material_ids.insert(material as *const Material as usize, id);
+        }
+
+        AovTracer {
+            scene,
+            ambient_occlusion: ambient_occlusion.cloned().unwrap_or_default(),
+            material_ids,
+        }
+    }
+
+    /// Writes the value of each of `aovs` for a camera ray to the corresponding element of
+    /// `values`, given the ray's closest hit and the index of the object it hit. Where the ray
+    /// does not hit anything, all AOVs are zero except for the ambient occlusion, which is one.
+    pub fn evaluate(
+        &self,
+        aovs: &[Aov],
+        ray: &Ray,
+        hit: Option<(usize, &HitRecord)>,
+        rng: &mut dyn rand::RngCore,
+        values: &mut [Vec3],
+    ) {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let Some((index, hit)) = hit else {
+            for (aov, value) in aovs.iter().zip(values) {
+                *value = match aov {
+                    Aov::AmbientOcclusion => Vec3::new(1.0, 1.0, 1.0),
+                    _ => zero,
+                };
+            }
+            return;
+        };
+
+        let p_hit = ray.at(hit.distance);
+        let material = hit.surface_material();
+        // The side of the surface that the camera sees
+        let facing = tracer::facing_normal(&hit.normal, ray.direction());
+        let gray = |v: f64| Vec3::new(v, v, v);
+        for (aov, value) in aovs.iter().zip(values) {
+            *value = match aov {
+                Aov::Depth => gray(hit.distance),
+                Aov::Normal => hit.normal,
+                Aov::Albedo => hit.color.unwrap_or(material.color),
+                Aov::ObjectId => gray(index as f64 + 1.0),
+                Aov::MaterialId => {
+                    let key = material as *const Material as usize;
+                    let id = self.material_ids.get(&key).copied().unwrap_or(0);
+                    gray(id as f64)
+                }
+                Aov::Position => p_hit,
+                Aov::AmbientOcclusion => gray(tracer::ambient_occlusion(
+                    &self.scene.objects,
+                    &tracer::offset_ray_origin(&p_hit, &facing, &facing),
+                    &facing,
+                    ray.time,
+                    rng,
+                    &self.ambient_occlusion,
+                )),
+            };
+        }
+    }
+}
+
+fn material_params(material: &Material) -> [u64; 7] {
+    [
+        material.color.x.to_bits(),
+        material.color.y.to_bits(),
+        material.color.z.to_bits(),
+        material.reflectance.to_bits(),
+        material.specular_strength.to_bits(),
+        material.specular_exponent.to_bits(),
+        material.opacity.to_bits(),
+    ]
+}
@@ -0,0 +1,177 @@
+use crate::math3d::Vec3;
+
+/// How points on the image plane are mapped to camera rays.
+#[derive(Debug, Copy, Clone)]
+pub enum Projection {
+    /// Pinhole camera looking along +z with a horizontal field of view of 90 degrees
+    Perspective,
+    /// Stereoscopic 360 degree panorama. The upper half of the image is an equirectangular
+    /// projection for the left eye, the lower half for the right eye.
+    Stereo360,
+}
+
+impl std::str::FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "perspective" => Ok(Projection::Perspective),
+            "stereo360" => Ok(Projection::Stereo360),
+            _ => Err(format!("Unknown projection '{s}'")),
+        }
+    }
+}
+
+/// The outline of the aperture, which determines the shape of out-of-focus highlights.
+#[derive(Debug, Copy, Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// A regular polygon formed by the given number of aperture blades, at least three
+    Polygon {
+        blades: u32,
+    },
+}
+
+impl ApertureShape {
+    /// Maps a point (`u1`, `u2`) of the unit square to a point of the aperture, which has a
+    /// (circum-)radius of one. Uniformly distributed points yield uniformly distributed points
+    /// on the aperture.
+    fn sample(self, u1: f64, u2: f64) -> (f64, f64) {
+        use std::f64::consts::PI;
+        match self {
+            ApertureShape::Circle => {
+                let r = u1.sqrt();
+                let phi = 2.0 * PI * u2;
+                (r * phi.cos(), r * phi.sin())
+            }
+            ApertureShape::Polygon { blades } => {
+                // Pick one of the triangles between the center and two neighboring corners,
+                // then a point within it
+                let blades = blades as f64;
+                let segment = (u1 * blades).floor().min(blades - 1.0);
+                let r = (u1 * blades - segment).sqrt();
+                let phi0 = 2.0 * PI * segment / blades;
+                let phi1 = 2.0 * PI * (segment + 1.0) / blades;
+                let x = (1.0 - u2) * phi0.cos() + u2 * phi1.cos();
+                let y = (1.0 - u2) * phi0.sin() + u2 * phi1.sin();
+                (r * x, r * y)
+            }
+        }
+    }
+}
+
+/// A lens of negligible thickness. Points at `focal_distance` are in focus, everything else is
+/// blurred the more the larger the aperture is.
+#[derive(Debug, Copy, Clone)]
+pub struct ThinLens {
+    pub aperture_radius: f64,
+    /// For the perspective projection, the distance of the focal plane along the view
+    /// direction. For panoramas, the radius of the focal sphere around the eye.
+    pub focal_distance: f64,
+    pub aperture_shape: ApertureShape,
+}
+
+pub struct Camera {
+    pub projection: Projection,
+    pub position: Vec3,
+    /// Image width in pixels
+    pub width: usize,
+    /// Image height in pixels
+    pub height: usize,
+    /// Lens for depth of field, `None` for a pinhole camera that has everything in focus
+    pub lens: Option<ThinLens>,
+    /// Points in time at which the shutter opens and closes. Moving objects are blurred along
+    /// their path in between.
+    pub shutter: (f64, f64),
+}
+
+impl Camera {
+    pub fn new(projection: Projection, width: usize, height: usize) -> Camera {
+        Camera {
+            projection,
+            position: Vec3::new(0.0, 0.0, 0.0),
+            width,
+            height,
+            lens: None,
+            shutter: (0.0, 0.0),
+        }
+    }
+
+    /// Returns origin and (normalized) direction of the ray through the point (`x`, `y`) of the
+    /// image plane, in pixel units. With a lens, the ray starts at a random point of the
+    /// aperture that is drawn from `rng`.
+    pub fn ray(&self, x: f64, y: f64, rng: &mut dyn rand::RngCore) -> (Vec3, Vec3) {
+        use rand::Rng;
+        let (src, dir) = self.pinhole_ray(x, y);
+        let Some(lens) = &self.lens else {
+            return (src, dir);
+        };
+
+        // All rays through the same point of the image plane meet at the point in focus. The
+        // lens lies in the image plane for the perspective projection, and perpendicular to the
+        // ray for panoramas.
+        let (focus, u_axis, v_axis) = match self.projection {
+            Projection::Perspective => (
+                src + dir * (lens.focal_distance / dir.z),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ),
+            Projection::Stereo360 => {
+                let up = if dir.y.abs() < 0.999 {
+                    Vec3::new(0.0, 1.0, 0.0)
+                } else {
+                    Vec3::new(1.0, 0.0, 0.0)
+                };
+                let u_axis = up.cross(dir).normalized();
+                (src + dir * lens.focal_distance, u_axis, dir.cross(u_axis))
+            }
+        };
+        let (u, v) = lens
+            .aperture_shape
+            .sample(rng.gen::<f64>(), rng.gen::<f64>());
+        let src = src + (u_axis * u + v_axis * v) * lens.aperture_radius;
+        (src, (focus - src).normalized())
+    }
+
+    /// Returns a random point in time while the shutter is open.
+    pub fn time(&self, rng: &mut dyn rand::RngCore) -> f64 {
+        use rand::Rng;
+        let (open, close) = self.shutter;
+        if close > open {
+            open + (close - open) * rng.gen::<f64>()
+        } else {
+            open
+        }
+    }
+
+    fn pinhole_ray(&self, x: f64, y: f64) -> (Vec3, Vec3) {
+        let width = self.width as f64;
+        let height = self.height as f64;
+
+        match self.projection {
+            Projection::Perspective => {
+                let dir = Vec3::new(x - width / 2.0, y - height / 2.0, width / 2.0).normalized();
+                (self.position, dir)
+            }
+            Projection::Stereo360 => {
+                let height_half = height / 2.0;
+                let y_rel = (y % height_half) / height_half;
+                let top = y < height_half;
+                let y_rad = (y_rel - 0.5) * std::f64::consts::PI;
+
+                let x_rel = x / width;
+                let x_rad = (x_rel - 0.5) * 2.0 * std::f64::consts::PI;
+
+                let dir = Vec3::new(
+                    x_rad.sin() * y_rad.cos(),
+                    y_rad.sin(),
+                    x_rad.cos() * y_rad.cos(),
+                )
+                .normalized();
+
+                let eye_offset = Vec3::new(if top { -10.0 } else { 10.0 }, 0.0, 0.0);
+                (self.position + eye_offset, dir)
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::aov::Aov;
+use crate::framebuffer::Framebuffer;
+use crate::tiles::TileOrder;
+
+const MAGIC: &[u8; 8] = b"RTCHKPT\0";
+const VERSION: u32 = 3;
+
+/// The complete state of an interrupted render. Since the random numbers of every sample are
+/// derived from `seed`, the pixel and the pass, the seed and the current pass fully describe the
+/// RNG state, and resuming from a checkpoint yields the same image as an uninterrupted render.
+pub struct Checkpoint {
+    pub seed: u64,
+    /// The pass that was being rendered. Tiles whose pixels already have `pass + 1` samples had
+    /// been completed in this pass.
+    pub pass: usize,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// The other options that the samples depend on, in a form chosen by the program that
+    /// rendered them. A render can only be resumed with the same options.
+    pub options: String,
+    pub framebuffer: Framebuffer,
+}
+
+/// Writes a checkpoint. The data is written to a temporary file first, so that an existing
+/// checkpoint is only replaced by a complete new one. `options` are stored for
+/// `Checkpoint::options`.
+pub fn save(
+    path: &Path,
+    seed: u64,
+    pass: usize,
+    tile_size: usize,
+    tile_order: TileOrder,
+    options: &str,
+    framebuffer: &Framebuffer,
+) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&(framebuffer.width() as u32).to_le_bytes())?;
+    w.write_all(&(framebuffer.height() as u32).to_le_bytes())?;
+    w.write_all(&seed.to_le_bytes())?;
+    w.write_all(&(pass as u32).to_le_bytes())?;
+    w.write_all(&(tile_size as u32).to_le_bytes())?;
+    w.write_all(&[tile_order as u8])?;
+    w.write_all(&[framebuffer.aovs().len() as u8])?;
+    for &aov in framebuffer.aovs() {
+        w.write_all(&[aov as u8])?;
+    }
+    w.write_all(&(options.len() as u32).to_le_bytes())?;
+    w.write_all(options.as_bytes())?;
+    framebuffer.write_to(&mut w)?;
+    w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    std::fs::rename(&tmp_path, path)
+}
+
+pub fn load(path: &Path) -> std::io::Result<Checkpoint> {
+    let mut r = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a checkpoint file"));
+    }
+    let version = read_u32(&mut r)?;
+    if version != VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported checkpoint version {version}"),
+        ));
+    }
+
+    let width = read_u32(&mut r)? as usize;
+    let height = read_u32(&mut r)? as usize;
+    let mut seed = [0u8; 8];
+    r.read_exact(&mut seed)?;
+    let pass = read_u32(&mut r)? as usize;
+    let tile_size = read_u32(&mut r)? as usize;
+    if tile_size == 0 {
+        return Err(Error::new(ErrorKind::InvalidData, "Invalid tile size"));
+    }
+    let mut tile_order = [0u8; 1];
+    r.read_exact(&mut tile_order)?;
+    let tile_order = match tile_order[0] {
+        0 => TileOrder::Scanline,
+        1 => TileOrder::Spiral,
+        2 => TileOrder::Hilbert,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid tile order")),
+    };
+    let mut num_aovs = [0u8; 1];
+    r.read_exact(&mut num_aovs)?;
+    let mut aovs = vec![0u8; num_aovs[0] as usize];
+    r.read_exact(&mut aovs)?;
+    let aovs = aovs
+        .into_iter()
+        .map(|aov| Aov::ALL.get(aov as usize).copied())
+        .collect::<Option<Vec<Aov>>>()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid AOV"))?;
+    let options_len = read_u32(&mut r)? as u64;
+    let mut options = String::new();
+    r.by_ref().take(options_len).read_to_string(&mut options)?;
+    if options.len() as u64 != options_len {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated options"));
+    }
+
+    Ok(Checkpoint {
+        seed: u64::from_le_bytes(seed),
+        pass,
+        tile_size,
+        tile_order,
+        options,
+        framebuffer: Framebuffer::read_from(&mut r, width, height, &aovs)?,
+    })
+}
+
+fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
@@ -0,0 +1,130 @@
+use crate::ray::Ray;
+use crate::scene_objects::{HitRecord, Interval, Material, Object3D, SceneObject};
+
+#[derive(Debug, Copy, Clone)]
+enum Operation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl Operation {
+    /// Whether a point is inside the combined object, given whether it is inside the operands
+    fn contains(&self, inside_a: bool, inside_b: bool) -> bool {
+        match self {
+            Operation::Union => inside_a || inside_b,
+            Operation::Intersection => inside_a && inside_b,
+            Operation::Difference => inside_a && !inside_b,
+        }
+    }
+}
+
+/// Constructive solid geometry: an object combined from two closed objects by union,
+/// intersection or difference. The operands are combined by the intervals in which a ray is
+/// inside them, so they may be any objects reporting those, including other `Csg` nodes. Every
+/// surface keeps the material of the operand it belongs to.
+pub struct Csg {
+    operation: Operation,
+    a: Box<SceneObject>,
+    b: Box<SceneObject>,
+}
+
+impl Csg {
+    /// Everything that is inside `a` or `b`
+    pub fn union(
+        a: impl Object3D + Send + Sync + 'static,
+        b: impl Object3D + Send + Sync + 'static,
+    ) -> Csg {
+        Csg::new(Operation::Union, a, b)
+    }
+
+    /// Everything that is inside both `a` and `b`
+    pub fn intersection(
+        a: impl Object3D + Send + Sync + 'static,
+        b: impl Object3D + Send + Sync + 'static,
+    ) -> Csg {
+        Csg::new(Operation::Intersection, a, b)
+    }
+
+    /// Everything that is inside `a` but not inside `b`. The surface of `b` that is carved out of
+    /// `a` is seen from the inside, so its normals are flipped.
+    pub fn difference(
+        a: impl Object3D + Send + Sync + 'static,
+        b: impl Object3D + Send + Sync + 'static,
+    ) -> Csg {
+        Csg::new(Operation::Difference, a, b)
+    }
+
+    fn new(
+        operation: Operation,
+        a: impl Object3D + Send + Sync + 'static,
+        b: impl Object3D + Send + Sync + 'static,
+    ) -> Csg {
+        Csg {
+            operation,
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+}
+
+impl Object3D for Csg {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        // From inside the object, the ray hits where it leaves
+        self.intervals(ray)
+            .into_iter()
+            .flat_map(|interval| [interval.enter, interval.exit])
+            .find(|hit| hit.distance > ray.t_min)
+            .filter(|hit| hit.distance < ray.t_max)
+    }
+
+    /// The material of the first operand. Hits report the operand they belong to, so this is
+    /// only used where the object is not hit.
+    fn get_material(&self) -> &Material {
+        self.a.get_material()
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        let mut materials = self.a.materials();
+        materials.extend(self.b.materials());
+        materials
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        // Where the ray enters or leaves one of the operands, tagged by whether it is `a`
+        let mut events: Vec<(HitRecord, bool)> = Vec::new();
+        for (object, is_a) in [(&self.a, true), (&self.b, false)] {
+            for interval in object.intervals(ray) {
+                events.push((interval.enter, is_a));
+                events.push((interval.exit, is_a));
+            }
+        }
+        events.sort_by(|(a, _), (b, _)| a.distance.total_cmp(&b.distance));
+
+        // Sweep along the ray, the combined object's surface is where its inside changes
+        let (mut inside_a, mut inside_b) = (false, false);
+        let mut enter = None;
+        let mut intervals = Vec::new();
+        for (mut hit, is_a) in events {
+            let was_inside = self.operation.contains(inside_a, inside_b);
+            if is_a {
+                inside_a = !inside_a;
+            } else {
+                inside_b = !inside_b;
+                if let Operation::Difference = self.operation {
+                    hit.normal = -hit.normal;
+                }
+            }
+            match (was_inside, self.operation.contains(inside_a, inside_b)) {
+                (false, true) => enter = Some(hit),
+                (true, false) => {
+                    if let Some(enter) = enter.take() {
+                        intervals.push(Interval { enter, exit: hit });
+                    }
+                }
+                _ => {}
+            }
+        }
+        intervals
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use rust_raytracer::animation::Keyframes;
+use rust_raytracer::environment::Environment;
+use rust_raytracer::math3d::{Rotation, Transform, Vec3};
+use rust_raytracer::mesh::TriangleMesh;
+use rust_raytracer::scene::Scene;
+use rust_raytracer::scene_objects::{Instance, Material, Plane, SceneObject, Sphere};
+
+/// Length of the demo animation in seconds
+pub const DURATION: f64 = 4.0;
+
+/// The demo scene: a bunny and some spheres on a green plane, surrounded by random spheres.
+///
+/// The scene is animated: the bunny hops, sways and changes its color, while the camera moves in
+/// front of it and the light wanders from left to right. At time zero, it is at rest.
+pub struct DemoScene {
+    /// Loaded only once and shared by all frames
+    bunny: Arc<SceneObject>,
+    /// Transform of the bunny and its eyes
+    bunny_motion: Keyframes<Transform>,
+    bunny_color: Keyframes<Vec3>,
+    light_dir: Keyframes<Vec3>,
+    camera_path: Keyframes<Vec3>,
+}
+
+impl DemoScene {
+    pub fn new() -> DemoScene {
+        let bunny = TriangleMesh::from_obj_file(
+            "data/bunny.obj",
+            Material::new(Vec3::new(0.8, 0.2, 0.2), 0.0, 0.3, 32.0),
+        )
+        .expect("Valid OBJ");
+
+        // The eyes are much closer to the camera than the bunny and only line up with it in the
+        // projection. Rotating both around the camera keeps them in place relative to each other.
+        let pose = |axis: Vec3, angle: f64| Transform {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Rotation::from_axis_angle(axis, angle),
+            scale: 1.0,
+        };
+        let lift = |angle: f64| pose(Vec3::new(1.0, 0.0, 0.0), angle);
+        let sway = |angle: f64| pose(Vec3::new(0.0, 1.0, 0.0), angle);
+        let rest = lift(0.0);
+        // Quick take-off and landing, slow at the top of the jump, then swaying from side to side
+        let bunny_motion = Keyframes::bezier(vec![
+            (0.0, rest, rest, rest),
+            (1.0, rest, rest, lift(0.1)),
+            (1.5, lift(0.12), lift(0.12), lift(0.12)),
+            (2.0, lift(0.1), rest, rest),
+            (2.5, rest, rest, rest),
+            (3.0, sway(0.08), sway(0.1), sway(0.12)),
+            (3.5, sway(-0.12), sway(-0.1), sway(-0.08)),
+            (4.0, rest, rest, rest),
+        ]);
+
+        DemoScene {
+            bunny: Arc::new(bunny),
+            bunny_motion,
+            bunny_color: Keyframes::new(vec![
+                (0.0, Vec3::new(0.8, 0.2, 0.2)),
+                (2.0, Vec3::new(0.8, 0.2, 0.2)),
+                (3.0, Vec3::new(0.6, 0.2, 0.8)),
+                (4.0, Vec3::new(0.8, 0.2, 0.2)),
+            ]),
+            light_dir: Keyframes::linear(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, -1.0, -1.0)),
+            camera_path: Keyframes::catmull_rom(vec![
+                (0.0, Vec3::new(0.0, 0.0, 0.0)),
+                (1.0, Vec3::new(-20.0, -5.0, 10.0)),
+                (2.0, Vec3::new(0.0, -10.0, 20.0)),
+                (3.0, Vec3::new(20.0, -5.0, 10.0)),
+                (4.0, Vec3::new(0.0, 0.0, 0.0)),
+            ]),
+        }
+    }
+
+    pub fn camera_position(&self, time: f64) -> Vec3 {
+        self.camera_path.at(time)
+    }
+
+    /// Builds the scene at `time`. Object motion is not evaluated here but per ray, so that
+    /// motion blur works within a frame.
+    pub fn scene(&self, time: f64) -> Scene {
+        let hopping = |object: Sphere| -> Box<SceneObject> {
+            Box::new(Instance::new(Arc::new(object), self.bunny_motion.clone()))
+        };
+
+        let mut objects: Vec<Box<SceneObject>> = vec![
+            hopping(Sphere::new(
+                Vec3::new(-100.0, -80.0, 400.0),
+                40.0,
+                Material::new_diffuse(Vec3::new(0.8, 0.8, 0.8)),
+            )),
+            hopping(Sphere::new(
+                Vec3::new(100.0, -80.0, 400.0),
+                40.0,
+                Material::new_diffuse(Vec3::new(0.8, 0.8, 0.8)),
+            )),
+            /*  Box::new(Sphere::new(
+                Vec3::new(0.0, 50.0, 700.0),
+                350.0,
+                Material::new(Vec3::new(0.8, 0.8, 0.0), 0.1, 1.0, 10.0),
+            )),*/
+            hopping(Sphere::new(
+                Vec3::new(100.0, -80.0, 370.0),
+                20.0,
+                Material::new(Vec3::new(0.1, 0.1, 0.1), 0.0, 1.0, 20.0),
+            )),
+            hopping(Sphere::new(
+                Vec3::new(-100.0, -80.0, 370.0),
+                20.0,
+                Material::new(Vec3::new(0.1, 0.1, 0.1), 0.0, 1.0, 20.0),
+            )),
+            Box::new(Plane::new(
+                Vec3::new(0.0, 200.0, 0.0),
+                Vec3::new(0.0, -1.0, 0.0),
+                Material::new_diffuse(Vec3::new(0.1, 0.5, 0.1)),
+            )),
+        ];
+
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(44);
+        use rand::Rng;
+        for _i in 0..800 {
+            let center = Vec3::new(
+                (rng.gen::<f64>() - 0.5) * 4000.0,
+                200.0 - (rng.gen::<f64>()) * 2000.0,
+                (rng.gen::<f64>() - 0.5) * 4000.0,
+            );
+
+            let dist = center.len();
+            objects.push(Box::new(Sphere::new(
+                center,
+                rng.gen::<f64>() * dist / 5.0,
+                Material::new(
+                    Vec3::new(
+                        0.5 + 0.5 * rng.gen::<f64>(),
+                        0.5 + 0.5 * rng.gen::<f64>(),
+                        0.5 + 0.5 * rng.gen::<f64>(),
+                    ),
+                    0.3,
+                    1.0,
+                    50.0,
+                ),
+            )));
+        }
+
+        objects.push(Box::new(
+            Instance::new(self.bunny.clone(), self.bunny_motion.clone())
+                .with_material(Material::new(self.bunny_color.at(time), 0.0, 0.3, 32.0)),
+        ));
+
+        Scene {
+            objects,
+            light_dir: self.light_dir.at(time).normalized(),
+            environment: Environment::default(),
+            fog: None,
+            volumes: Vec::new(),
+        }
+    }
+}
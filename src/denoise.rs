@@ -0,0 +1,181 @@
+use crate::aov::Aov;
+use crate::framebuffer::Framebuffer;
+use crate::math3d::Vec3;
+
+/// Parameters of the denoising filter. Larger sigmas blur more across the respective edges.
+#[derive(Debug, Clone)]
+pub struct DenoiseSettings {
+    /// Number of filter iterations. The filter covers (2^iterations * 4 + 1)² pixels.
+    pub iterations: u32,
+    /// Tolerance for differences in the lighting, which is reduced with every iteration
+    pub sigma_color: f64,
+    /// Tolerance for differences in the surface normals
+    pub sigma_normal: f64,
+    /// Tolerance for differences in the surface colors
+    pub sigma_albedo: f64,
+    /// Tolerance for differences in depth, relative to the depth of the filtered pixel
+    pub sigma_depth: f64,
+    /// Number of worker threads, `None` to use one thread per CPU core
+    pub threads: Option<usize>,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        DenoiseSettings {
+            iterations: 5,
+            sigma_color: 0.5,
+            sigma_normal: 0.3,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.05,
+            threads: None,
+        }
+    }
+}
+
+/// The AOVs that guide the denoiser and need to be rendered for it
+pub const GUIDE_AOVS: [Aov; 3] = [Aov::Normal, Aov::Albedo, Aov::Depth];
+
+/// The resolved color and guide buffers of a framebuffer. They are copied out of the framebuffer
+/// so that the filter does not need to hold on to it.
+pub struct DenoiseInput {
+    width: usize,
+    height: usize,
+    colors: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    albedo: Vec<Vec3>,
+    depth: Vec<f64>,
+}
+
+impl DenoiseInput {
+    /// Returns `None` if the framebuffer does not contain all of the `GUIDE_AOVS`.
+    pub fn from_framebuffer(framebuffer: &Framebuffer) -> Option<DenoiseInput> {
+        Some(DenoiseInput {
+            width: framebuffer.width(),
+            height: framebuffer.height(),
+            colors: framebuffer.colors(),
+            normals: framebuffer.aov(Aov::Normal)?,
+            albedo: framebuffer.aov(Aov::Albedo)?,
+            depth: framebuffer.aov(Aov::Depth)?.iter().map(|d| d.x).collect(),
+        })
+    }
+
+    /// Filters the colors with an edge-avoiding à-trous wavelet transform (Dammertz et al.,
+    /// "Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination Filtering"). Every
+    /// iteration applies a sparse 5x5 B-spline kernel with twice the spacing of the previous
+    /// one, whose weights are reduced across edges in the guide buffers.
+    ///
+    /// To keep the surface colors sharp, only the lighting is filtered: the colors are divided
+    /// by the albedo before filtering and multiplied with it afterwards.
+    pub fn denoise(&self, settings: &DenoiseSettings) -> Vec<Vec3> {
+        let mut lighting: Vec<Vec3> = self
+            .colors
+            .iter()
+            .zip(&self.albedo)
+            .map(|(col, albedo)| demodulate(col, albedo))
+            .collect();
+
+        let num_threads = settings.threads.unwrap_or_else(num_cpus::get);
+        let rows_per_thread = self.height.div_ceil(num_threads).max(1);
+        for iteration in 0..settings.iterations {
+            let mut filtered = vec![Vec3::new(0.0, 0.0, 0.0); lighting.len()];
+            std::thread::scope(|scope| {
+                let chunks = filtered.chunks_mut(rows_per_thread * self.width);
+                for (chunk_idx, chunk) in chunks.enumerate() {
+                    let lighting = &lighting;
+                    scope.spawn(move || {
+                        let first_row = chunk_idx * rows_per_thread;
+                        for (i, out) in chunk.iter_mut().enumerate() {
+                            let (x, y) = (i % self.width, first_row + i / self.width);
+                            *out = self.filter_pixel(lighting, x, y, iteration, settings);
+                        }
+                    });
+                }
+            });
+            lighting = filtered;
+        }
+
+        lighting
+            .iter()
+            .zip(&self.albedo)
+            .map(|(l, albedo)| remodulate(l, albedo))
+            .collect()
+    }
+
+    fn filter_pixel(
+        &self,
+        lighting: &[Vec3],
+        x: usize,
+        y: usize,
+        iteration: u32,
+        settings: &DenoiseSettings,
+    ) -> Vec3 {
+        const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+        let step = 1isize << iteration;
+        // The lighting becomes smoother with every iteration, so differences that remain are
+        // more likely to be actual features
+        let sigma_color = settings.sigma_color / (1u64 << iteration) as f64;
+
+        let p = y * self.width + x;
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let mut weight_sum = 0.0;
+        for (j, ky) in KERNEL.iter().enumerate() {
+            let qy = y as isize + (j as isize - 2) * step;
+            if qy < 0 || qy >= self.height as isize {
+                continue;
+            }
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let qx = x as isize + (i as isize - 2) * step;
+                if qx < 0 || qx >= self.width as isize {
+                    continue;
+                }
+                let q = qy as usize * self.width + qx as usize;
+
+                let color_dist = (lighting[p] - lighting[q]).squared_length();
+                let normal_dist = (self.normals[p] - self.normals[q]).squared_length();
+                let albedo_dist = (self.albedo[p] - self.albedo[q]).squared_length();
+                let depth_dist =
+                    (self.depth[p] - self.depth[q]).abs() / self.depth[p].max(self.depth[q]);
+                let depth_dist = if depth_dist.is_finite() {
+                    depth_dist
+                } else {
+                    0.0
+                };
+
+                let weight = kx
+                    * ky
+                    * f64::exp(
+                        -color_dist / (sigma_color * sigma_color)
+                            - normal_dist / (settings.sigma_normal * settings.sigma_normal)
+                            - albedo_dist / (settings.sigma_albedo * settings.sigma_albedo)
+                            - depth_dist / settings.sigma_depth,
+                    );
+                sum = sum + lighting[q] * weight;
+                weight_sum += weight;
+            }
+        }
+
+        // The weight of the center pixel itself is always positive
+        sum * (1.0 / weight_sum)
+    }
+}
+
+/// Albedo below this is treated as black, to avoid amplifying noise on dark surfaces
+const MIN_ALBEDO: f64 = 1E-3;
+
+fn demodulate(color: &Vec3, albedo: &Vec3) -> Vec3 {
+    let div = |c: f64, a: f64| if a > MIN_ALBEDO { c / a } else { c };
+    Vec3::new(
+        div(color.x, albedo.x),
+        div(color.y, albedo.y),
+        div(color.z, albedo.z),
+    )
+}
+
+fn remodulate(lighting: &Vec3, albedo: &Vec3) -> Vec3 {
+    let mul = |l: f64, a: f64| if a > MIN_ALBEDO { l * a } else { l };
+    Vec3::new(
+        mul(lighting.x, albedo.x),
+        mul(lighting.y, albedo.y),
+        mul(lighting.z, albedo.z),
+    )
+}
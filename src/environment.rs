@@ -0,0 +1,503 @@
+use std::f64::consts::PI;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::math3d::Vec3;
+
+/// The light arriving from infinitely far away, seen by rays that leave the scene.
+#[derive(Clone)]
+pub enum Environment {
+    /// The same color in all directions
+    Color(Vec3),
+    /// An HDR image of the surroundings
+    Map(Arc<EnvironmentMap>),
+    /// An analytic daylight sky with a sun
+    Sky(Arc<Sky>),
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::Color(Vec3::new(0.0, 0.3, 0.8))
+    }
+}
+
+impl Environment {
+    /// Returns the light arriving from direction `dir`, which points away from the scene.
+    pub fn radiance(&self, dir: &Vec3) -> Vec3 {
+        match self {
+            Environment::Color(color) => *color,
+            Environment::Map(map) => map.radiance(dir),
+            Environment::Sky(sky) => sky.radiance(dir, true),
+        }
+    }
+
+    /// Like `radiance()`, but without the sun of a sky, whose light is already accounted for
+    /// by the directional light of the scene.
+    pub fn ambient_radiance(&self, dir: &Vec3) -> Vec3 {
+        match self {
+            Environment::Sky(sky) => sky.radiance(dir, false),
+            _ => self.radiance(dir),
+        }
+    }
+
+    /// Maps a point (`u1`, `u2`) of the unit square to a direction, so that uniformly
+    /// distributed points prefer the directions from which much light arrives at a surface with
+    /// `normal`. Returns the direction and its probability density per solid angle, or `None`
+    /// if no light arrives from anywhere.
+    pub fn sample(&self, normal: &Vec3, u1: f64, u2: f64) -> Option<(Vec3, f64)> {
+        match self {
+            Environment::Color(_) => {
+                let dir = normal.get_cosine_distributed_ray(u1, u2);
+                Some((dir, dir.dot(*normal).max(0.0) / PI))
+            }
+            Environment::Map(map) => map.sample(u1, u2),
+            Environment::Sky(sky) => sky.map.sample(u1, u2),
+        }
+    }
+}
+
+/// An equirectangular (latitude-longitude) image of the light arriving from all directions.
+/// The top row of the image is straight up, its center looks along the z axis.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    /// Rows from top to bottom
+    pixels: Vec<Vec3>,
+    /// Rotation around the vertical axis, in radians
+    rotation: f64,
+    intensity: f64,
+    /// Cumulative share of the pixels in the light arriving from the environment, starting
+    /// with zero
+    cdf: Vec<f64>,
+}
+
+impl EnvironmentMap {
+    /// Creates a map from its pixels, in rows from top to bottom. Panics if the number of
+    /// pixels does not match the size.
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> EnvironmentMap {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "The map has width * height pixels"
+        );
+        let mut cdf = Vec::with_capacity(pixels.len() + 1);
+        let mut sum = 0.0;
+        cdf.push(sum);
+        for (i, pixel) in pixels.iter().enumerate() {
+            // Rows near the poles cover a smaller solid angle
+            let theta = ((i / width) as f64 + 0.5) / height as f64 * PI;
+            sum += luminance(pixel).max(0.0) * theta.sin();
+            cdf.push(sum);
+        }
+
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation: 0.0,
+            intensity: 1.0,
+            cdf,
+        }
+    }
+
+    /// Loads a map from a Radiance HDR (`.hdr`) or a PFM (`.pfm`) file.
+    pub fn load(path: &Path) -> std::io::Result<EnvironmentMap> {
+        let data = std::fs::read(path)?;
+        let extension = path.extension().and_then(|e| e.to_str());
+        let (width, height, pixels) = match extension.map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("hdr") => parse_hdr(&data)?,
+            Some("pfm") => parse_pfm(&data)?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Unsupported environment map, expected a .hdr or .pfm file",
+                ))
+            }
+        };
+        if width == 0 || height == 0 {
+            return Err(invalid_data("The environment map is empty"));
+        }
+        Ok(EnvironmentMap::new(width, height, pixels))
+    }
+
+    /// Rotates the map around the vertical axis by `rotation` radians.
+    pub fn with_rotation(mut self, rotation: f64) -> EnvironmentMap {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Scales the brightness of the map by `intensity`.
+    pub fn with_intensity(mut self, intensity: f64) -> EnvironmentMap {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Returns the light arriving from direction `dir`, interpolated bilinearly between the
+    /// pixels.
+    pub fn radiance(&self, dir: &Vec3) -> Vec3 {
+        let (u, v) = direction_to_uv(dir, self.rotation);
+        let x = u * self.width as f64 - 0.5;
+        let y = (v * self.height as f64 - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+        // The map wraps around horizontally
+        let x0 = (x.floor() as isize).rem_euclid(self.width as isize) as usize;
+        let x1 = (x0 + 1) % self.width;
+        let y0 = y as usize;
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let pixel = |x: usize, y: usize| self.pixels[y * self.width + x];
+        let top = pixel(x0, y0) * (1.0 - fx) + pixel(x1, y0) * fx;
+        let bottom = pixel(x0, y1) * (1.0 - fx) + pixel(x1, y1) * fx;
+        (top * (1.0 - fy) + bottom * fy) * self.intensity
+    }
+
+    /// Picks a pixel with a probability proportional to the light arriving through it and
+    /// returns a direction within it and the probability density of that direction.
+    fn sample(&self, u1: f64, u2: f64) -> Option<(Vec3, f64)> {
+        let total = *self.cdf.last().unwrap();
+        let target = u1 * total;
+        // The pixel whose share of the distribution contains `target`
+        let i = (self.cdf.partition_point(|&c| c <= target) - 1).min(self.pixels.len() - 1);
+        let weight = self.cdf[i + 1] - self.cdf[i];
+        if weight <= 0.0 {
+            return None;
+        }
+
+        // The position of `target` within the pixel's share is uniformly distributed as well
+        let fx = (target - self.cdf[i]) / weight;
+        let u = ((i % self.width) as f64 + fx) / self.width as f64;
+        let v = ((i / self.width) as f64 + u2) / self.height as f64;
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+        // The density over the image is converted to a density per solid angle
+        let pdf = weight / total * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta);
+        Some((uv_to_direction(u, v, self.rotation), pdf))
+    }
+}
+
+/// Returns the position of `dir` in an equirectangular map, both coordinates between 0 and 1.
+fn direction_to_uv(dir: &Vec3, rotation: f64) -> (f64, f64) {
+    // The y axis points downwards
+    let theta = (-dir.y / dir.len()).clamp(-1.0, 1.0).acos();
+    let phi = dir.x.atan2(dir.z) + rotation;
+    ((0.5 + phi / (2.0 * PI)).rem_euclid(1.0), theta / PI)
+}
+
+fn uv_to_direction(u: f64, v: f64, rotation: f64) -> Vec3 {
+    let phi = (u - 0.5) * 2.0 * PI - rotation;
+    let theta = v * PI;
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        -theta.cos(),
+        theta.sin() * phi.cos(),
+    )
+}
+
+fn luminance(color: &Vec3) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// Angular radius of the sun disk in radians, about twice the real one so that it is not lost
+/// between the pixels
+const SUN_ANGULAR_RADIUS: f64 = 0.01;
+/// Radiance of the sun disk relative to the sky at the zenith
+const SUN_RADIANCE: f64 = 50.0;
+/// Luminance of the sky at the zenith, for an intensity of one
+const ZENITH_LUMINANCE: f64 = 0.4;
+/// Fraction of the sky's light that the ground below the horizon reflects
+const GROUND_ALBEDO: f64 = 0.3;
+/// Resolution of the map that the sky is tabulated in for importance sampling
+const SKY_MAP_WIDTH: usize = 128;
+const SKY_MAP_HEIGHT: usize = 64;
+
+/// A clear daylight sky following the analytic model of Preetham et al., "A Practical Analytic
+/// Model for Daylight" (1999). Below the horizon, the sky is reflected by a gray ground.
+pub struct Sky {
+    /// Direction towards the sun, normalized
+    sun_dir: Vec3,
+    intensity: f64,
+    /// Coefficients A to E of the Perez distribution of the luminance and the two chromaticity
+    /// coordinates
+    perez: [[f64; 5]; 3],
+    /// Luminance and chromaticity at the zenith, divided by their Perez distribution there
+    zenith: [f64; 3],
+    /// The sky without the sun, tabulated for importance sampling
+    map: EnvironmentMap,
+}
+
+impl Sky {
+    /// Creates a sky lit by the sun in direction `sun_dir`. `turbidity` describes the haze of
+    /// the atmosphere, from about 2 (clear) to 10 (hazy). `intensity` scales the brightness.
+    pub fn new(sun_dir: Vec3, turbidity: f64, intensity: f64) -> Sky {
+        let sun_dir = sun_dir.normalized();
+        // The model is only valid for the sun above the horizon
+        let theta_s = (-sun_dir.y).clamp(0.01, 1.0).acos();
+        let t = turbidity;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let (th, th2, th3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+        // The absolute luminance of the model is replaced by ZENITH_LUMINANCE
+        let zenith = [ZENITH_LUMINANCE * intensity, zenith_x, zenith_y];
+        let zenith = [0, 1, 2].map(|c| zenith[c] / perez_distribution(&perez[c], 1.0, theta_s));
+
+        let mut sky = Sky {
+            sun_dir,
+            intensity,
+            perez,
+            zenith,
+            map: EnvironmentMap::new(0, 0, Vec::new()),
+        };
+        let pixels = (0..SKY_MAP_WIDTH * SKY_MAP_HEIGHT)
+            .map(|i| {
+                let u = ((i % SKY_MAP_WIDTH) as f64 + 0.5) / SKY_MAP_WIDTH as f64;
+                let v = ((i / SKY_MAP_WIDTH) as f64 + 0.5) / SKY_MAP_HEIGHT as f64;
+                sky.radiance(&uv_to_direction(u, v, 0.0), false)
+            })
+            .collect();
+        sky.map = EnvironmentMap::new(SKY_MAP_WIDTH, SKY_MAP_HEIGHT, pixels);
+        sky
+    }
+
+    fn radiance(&self, dir: &Vec3, with_sun: bool) -> Vec3 {
+        let dir = dir.normalized();
+        if dir.y > 0.0 {
+            let mirrored = Vec3::new(dir.x, -dir.y, dir.z);
+            return self.radiance(&mirrored, false) * GROUND_ALBEDO;
+        }
+
+        let cos_theta = (-dir.y).max(1E-3);
+        let gamma = dir.dot(self.sun_dir).clamp(-1.0, 1.0).acos();
+        let [lum, x, y] = [0, 1, 2]
+            .map(|c| self.zenith[c] * perez_distribution(&self.perez[c], cos_theta, gamma));
+
+        // From the xyY color space to linear sRGB
+        let (cx, cz) = (x / y * lum, (1.0 - x - y) / y * lum);
+        let color = Vec3::new(
+            3.2406 * cx - 1.5372 * lum - 0.4986 * cz,
+            -0.9689 * cx + 1.8758 * lum + 0.0415 * cz,
+            0.0557 * cx - 0.2040 * lum + 1.0570 * cz,
+        );
+        if with_sun && gamma < SUN_ANGULAR_RADIUS {
+            let sun =
+                Vec3::new(1.0, 0.95, 0.85) * (SUN_RADIANCE * ZENITH_LUMINANCE * self.intensity);
+            color + sun
+        } else {
+            color
+        }
+    }
+}
+
+/// The Perez sky distribution for a view direction at an angle with the cosine `cos_theta` to
+/// the zenith and at angle `gamma` to the sun.
+fn perez_distribution(coeffs: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coeffs;
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Returns the next header line, without the line break.
+fn header_line<'a>(data: &'a [u8], pos: &mut usize) -> std::io::Result<&'a str> {
+    let len = data[*pos..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| invalid_data("Truncated header"))?;
+    let line =
+        std::str::from_utf8(&data[*pos..*pos + len]).map_err(|_| invalid_data("Invalid header"))?;
+    *pos += len + 1;
+    Ok(line.trim_end_matches('\r'))
+}
+
+fn parse_size(s: &str) -> std::io::Result<usize> {
+    s.parse().map_err(|_| invalid_data("Invalid image size"))
+}
+
+/// Reads a Radiance RGBE image, with or without run-length encoding. Only the standard
+/// orientation (rows from top to bottom, pixels from left to right) is supported.
+fn parse_hdr(data: &[u8]) -> std::io::Result<(usize, usize, Vec<Vec3>)> {
+    let mut pos = 0;
+    if !header_line(data, &mut pos)?.starts_with("#?") {
+        return Err(invalid_data("Not a Radiance HDR file"));
+    }
+    loop {
+        let line = header_line(data, &mut pos)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid_data("Unsupported HDR pixel format"));
+            }
+        }
+    }
+    let resolution = header_line(data, &mut pos)?;
+    let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (parse_size(width)?, parse_size(height)?),
+        _ => return Err(invalid_data("Unsupported HDR orientation")),
+    };
+
+    // Checked before any memory is reserved, so that a corrupt header cannot request more
+    // pixels than the file holds. The shortest scanline is run-length encoded with runs of 127
+    // equal values, two bytes per run and component.
+    let num_pixels = width
+        .checked_mul(height)
+        .ok_or_else(|| invalid_data("Invalid image size"))?;
+    let min_scanline_len = if (8..0x8000).contains(&width) {
+        4 + 8 * width.div_ceil(127)
+    } else {
+        width.saturating_mul(4)
+    };
+    if min_scanline_len.saturating_mul(height) > data.len() - pos {
+        return Err(invalid_data("Truncated HDR file"));
+    }
+    if num_pixels == 0 {
+        return Ok((width, height, Vec::new()));
+    }
+
+    let mut bytes = data[pos..].iter().copied();
+    let mut next = || {
+        bytes
+            .next()
+            .ok_or_else(|| invalid_data("Truncated HDR file"))
+    };
+    let mut pixels = Vec::with_capacity(num_pixels);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        let first = [next()?, next()?, next()?, next()?];
+        let encoded = (8..0x8000).contains(&width) && first[..2] == [2, 2] && first[2] < 0x80;
+        if encoded {
+            if ((first[2] as usize) << 8 | first[3] as usize) != width {
+                return Err(invalid_data("Invalid HDR scanline"));
+            }
+            // Each of the four components is run-length encoded separately
+            for c in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = next()? as usize;
+                    let run = if count > 128 { count - 128 } else { count };
+                    if run == 0 || x + run > width {
+                        return Err(invalid_data("Invalid HDR scanline"));
+                    }
+                    if count > 128 {
+                        let value = next()?;
+                        for pixel in &mut scanline[x..x + run] {
+                            pixel[c] = value;
+                        }
+                    } else {
+                        for pixel in &mut scanline[x..x + run] {
+                            pixel[c] = next()?;
+                        }
+                    }
+                    x += run;
+                }
+            }
+        } else {
+            scanline[0] = first;
+            for pixel in &mut scanline[1..] {
+                *pixel = [next()?, next()?, next()?, next()?];
+            }
+        }
+        pixels.extend(scanline.iter().map(rgbe_to_rgb));
+    }
+
+    Ok((width, height, pixels))
+}
+
+/// Converts a pixel with a shared exponent to floating point.
+fn rgbe_to_rgb(rgbe: &[u8; 4]) -> Vec3 {
+    let [r, g, b, e] = *rgbe;
+    if e == 0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+    let f = 2f64.powi(e as i32 - (128 + 8));
+    Vec3::new(
+        (r as f64 + 0.5) * f,
+        (g as f64 + 0.5) * f,
+        (b as f64 + 0.5) * f,
+    )
+}
+
+/// Reads a color (`PF`) or gray-scale (`Pf`) portable float map.
+fn parse_pfm(data: &[u8]) -> std::io::Result<(usize, usize, Vec<Vec3>)> {
+    let mut pos = 0;
+    let channels = match header_line(data, &mut pos)? {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid_data("Not a PFM file")),
+    };
+    let size = header_line(data, &mut pos)?;
+    let (width, height) = match size.split_whitespace().collect::<Vec<_>>()[..] {
+        [width, height] => (parse_size(width)?, parse_size(height)?),
+        _ => return Err(invalid_data("Invalid image size")),
+    };
+    let scale: f64 = header_line(data, &mut pos)?
+        .trim()
+        .parse()
+        .map_err(|_| invalid_data("Invalid PFM scale"))?;
+    // A negative scale denotes little endian data
+    let little_endian = scale < 0.0;
+
+    let values = &data[pos..];
+    let num_pixels = width
+        .checked_mul(height)
+        .ok_or_else(|| invalid_data("Invalid image size"))?;
+    if num_pixels.saturating_mul(channels * 4) > values.len() {
+        return Err(invalid_data("Truncated PFM file"));
+    }
+    let value = |i: usize| {
+        let bytes = values[i * 4..i * 4 + 4].try_into().unwrap();
+        if little_endian {
+            f32::from_le_bytes(bytes) as f64
+        } else {
+            f32::from_be_bytes(bytes) as f64
+        }
+    };
+
+    let mut pixels = Vec::with_capacity(num_pixels);
+    // PFM stores the rows from bottom to top
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let i = (y * width + x) * channels;
+            pixels.push(if channels == 3 {
+                Vec3::new(value(i), value(i + 1), value(i + 2))
+            } else {
+                Vec3::new(value(i), value(i), value(i))
+            });
+        }
+    }
+
+    Ok((width, height, pixels))
+}
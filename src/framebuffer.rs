@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::aov::Aov;
+use crate::math3d::Vec3;
+use crate::tiles::Tile;
+
+/// Accumulates the radiance samples of all render passes. Each pixel stores the sum of its
+/// samples and the number of samples taken, so that the image can be resolved at any time, even
+/// in the middle of a pass.
+///
+/// Besides the color, each pixel can hold the samples of a number of AOVs. The values of a pixel
+/// are stored next to each other, the color first, followed by the AOVs in the order given at
+/// creation.
+#[derive(Clone)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    aovs: Vec<Aov>,
+    sums: Vec<Vec3>,
+    samples: Vec<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer::with_aovs(width, height, &[])
+    }
+
+    pub fn with_aovs(width: usize, height: usize, aovs: &[Aov]) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            aovs: aovs.to_vec(),
+            sums: vec![Vec3::new(0.0, 0.0, 0.0); width * height * (1 + aovs.len())],
+            samples: vec![0; width * height],
+        }
+    }
+
+    /// Adds one sample for each pixel of `tile`. `values` is tightly packed in the tile's size
+    /// and holds `channels()` values per pixel.
+    pub fn add_tile(&mut self, tile: &Tile, values: &[Vec3]) {
+        let channels = self.channels();
+        for (row_idx, row) in values.chunks(tile.width * channels).enumerate() {
+            let start = (tile.y + row_idx) * self.width + tile.x;
+            for (i, pixel) in row.chunks(channels).enumerate() {
+                let idx = start + i;
+                let sums = &mut self.sums[idx * channels..(idx + 1) * channels];
+                sums[0] = sums[0] + pixel[0];
+                for (k, aov) in self.aovs.iter().enumerate() {
+                    if aov.is_averaged() {
+                        sums[k + 1] = sums[k + 1] + pixel[k + 1];
+                    } else if self.samples[idx] == 0 {
+                        sums[k + 1] = pixel[k + 1];
+                    }
+                }
+                self.samples[idx] += 1;
+            }
+        }
+    }
+
+    /// The AOVs that are stored in addition to the color
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    /// Number of values stored per pixel: the color and the AOVs
+    pub fn channels(&self) -> usize {
+        1 + self.aovs.len()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the number of samples that have been taken for the given pixel.
+    pub fn samples(&self, x: usize, y: usize) -> u32 {
+        self.samples[y * self.width + x]
+    }
+
+    /// Serializes the sample sums and counts of all pixels, see `read_from()`.
+    pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+        for (sums, samples) in self.sums.chunks(self.channels()).zip(&self.samples) {
+            for sum in sums {
+                w.write_all(&sum.x.to_le_bytes())?;
+                w.write_all(&sum.y.to_le_bytes())?;
+                w.write_all(&sum.z.to_le_bytes())?;
+            }
+            w.write_all(&samples.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Restores a framebuffer of the given size and AOVs that was serialized with `write_to()`.
+    pub fn read_from(
+        r: &mut impl Read,
+        width: usize,
+        height: usize,
+        aovs: &[Aov],
+    ) -> std::io::Result<Self> {
+        let mut fb = Framebuffer::with_aovs(width, height, aovs);
+        let channels = fb.channels();
+        let mut f64_buf = [0u8; 8];
+        let mut u32_buf = [0u8; 4];
+        let mut read_f64 = |r: &mut dyn Read| -> std::io::Result<f64> {
+            r.read_exact(&mut f64_buf)?;
+            Ok(f64::from_le_bytes(f64_buf))
+        };
+        for (sums, samples) in fb.sums.chunks_mut(channels).zip(&mut fb.samples) {
+            for sum in sums {
+                *sum = Vec3::new(read_f64(r)?, read_f64(r)?, read_f64(r)?);
+            }
+            r.read_exact(&mut u32_buf)?;
+            *samples = u32::from_le_bytes(u32_buf);
+        }
+        Ok(fb)
+    }
+
+    /// Returns the average of the samples of every pixel in the given channel. Pixels without
+    /// samples are black.
+    fn resolve(&self, channel: usize, averaged: bool) -> Vec<Vec3> {
+        let channels = self.channels();
+        (0..self.width * self.height)
+            .map(|i| match self.samples[i] {
+                0 => Vec3::new(0.0, 0.0, 0.0),
+                n if averaged => self.sums[i * channels + channel] * (1.0 / n as f64),
+                _ => self.sums[i * channels + channel],
+            })
+            .collect()
+    }
+
+    /// Returns the color of every pixel, averaged over its samples.
+    pub fn colors(&self) -> Vec<Vec3> {
+        self.resolve(0, true)
+    }
+
+    /// Returns the values of `aov` for every pixel, or `None` if the AOV is not rendered.
+    pub fn aov(&self, aov: Aov) -> Option<Vec<Vec3>> {
+        let idx = self.aovs.iter().position(|&a| a == aov)?;
+        Some(self.resolve(idx + 1, aov.is_averaged()))
+    }
+
+    /// Averages the samples of every pixel and converts the result to 8-bit RGBA.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        tone_map(&self.colors())
+    }
+}
+
+/// Converts linear colors to 8-bit RGBA.
+pub fn tone_map(colors: &[Vec3]) -> Vec<u8> {
+    let mut data = vec![0; colors.len() * 4];
+    for (pixel, col) in data.chunks_mut(4).zip(colors) {
+        // Transform colors from physical to perceptual
+        let col = Vec3::new(
+            col.x.clamp(0.0, 1.0).sqrt(),
+            col.y.clamp(0.0, 1.0).sqrt(),
+            col.z.clamp(0.0, 1.0).sqrt(),
+        );
+        set_color(pixel, &col);
+    }
+    data
+}
+
+fn set_color(pixel: &mut [u8], col: &Vec3) {
+    pixel[0] = (col.x * 255.0) as u8;
+    pixel[1] = (col.y * 255.0) as u8;
+    pixel[2] = (col.z * 255.0) as u8;
+    pixel[3] = 255;
+}
+
+fn write_png(path: &Path, width: usize, height: usize, rgba: &[u8]) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let w = BufWriter::new(file);
+
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+/// Writes an RGBA image to a PNG file. The image is written to a temporary file first, so that
+/// `path` always contains a complete image even if the process is killed while writing.
+pub fn save_png(path: &Path, width: usize, height: usize, rgba: &[u8]) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    write_png(Path::new(&tmp_path), width, height, rgba)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Writes an image with floating point values to a PFM (portable float map) file, without any
+/// tone mapping. Like `save_png()`, the file is replaced atomically.
+pub fn save_pfm(path: &Path, width: usize, height: usize, pixels: &[Vec3]) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+    // A negative scale denotes little endian data
+    write!(w, "PF\n{width} {height}\n-1.0\n")?;
+    // PFM stores the rows from bottom to top
+    for row in pixels.chunks(width).rev() {
+        for pixel in row {
+            for v in [pixel.x, pixel.y, pixel.z] {
+                w.write_all(&(v as f32).to_le_bytes())?;
+            }
+        }
+    }
+    w.flush()?;
+    drop(w);
+    std::fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,56 @@
+//! A basic raytracer.
+//!
+//! A render combines a [`Scene`](scene::Scene) of objects, a [`Camera`](camera::Camera) and
+//! [`RenderSettings`](render::RenderSettings):
+//!
+//! ```no_run
+//! use rust_raytracer::camera::{Camera, Projection};
+//! use rust_raytracer::framebuffer;
+//! use rust_raytracer::math3d::Vec3;
+//! use rust_raytracer::render::{render, RenderSettings};
+//! use rust_raytracer::scene::Scene;
+//! use rust_raytracer::scene_objects::{Material, Sphere};
+//!
+//! let mut scene = Scene::new();
+//! scene.add(Sphere::new(
+//!     Vec3::new(0.0, 0.0, 200.0),
+//!     50.0,
+//!     Material::new_diffuse(Vec3::new(0.8, 0.2, 0.2)),
+//! ));
+//!
+//! let camera = Camera::new(Projection::Perspective, 320, 240);
+//! let image = render(&scene, &camera, &RenderSettings::default());
+//! framebuffer::save_png("image.png".as_ref(), 320, 240, &image.to_rgba8()).unwrap();
+//! ```
+//!
+//! The coordinate system is left-handed with the y axis pointing downwards.
+
+extern crate num_cpus;
+extern crate png;
+extern crate rand;
+
+pub mod animation;
+pub mod aov;
+pub mod camera;
+pub mod checkpoint;
+pub mod csg;
+pub mod denoise;
+pub mod environment;
+pub mod framebuffer;
+pub mod math3d;
+pub mod media;
+pub mod mesh;
+pub mod mtl;
+pub mod ply;
+pub mod primitives;
+pub mod ray;
+pub mod render;
+pub mod scene;
+pub mod scene_objects;
+pub mod sdf;
+pub mod simd;
+pub mod stats;
+pub mod stl;
+pub mod texture;
+pub mod tiles;
+pub mod tracer;
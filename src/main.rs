@@ -1,308 +1,472 @@
-extern crate num_cpus;
-extern crate png;
 extern crate rand;
+extern crate rust_raytracer;
 
-mod math3d;
-mod mesh;
-mod scene_objects;
+mod demo;
+mod options;
+mod progress;
 
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-
-use math3d::Vec3;
-use mesh::TriangleMesh;
-use scene_objects::{Material, SceneObject};
+use std::time::Instant;
+
+use rust_raytracer::aov::Aov;
+use rust_raytracer::camera::{Camera, ThinLens};
+use rust_raytracer::checkpoint;
+use rust_raytracer::denoise::{self, DenoiseInput, DenoiseSettings};
+use rust_raytracer::environment::{Environment, EnvironmentMap, Sky};
+use rust_raytracer::framebuffer::{self, Framebuffer};
+use rust_raytracer::media::Fog;
+use rust_raytracer::render::{self, RenderMode, RenderObserver, RenderSettings};
+use rust_raytracer::stats::{self, Phase, Stats};
+use rust_raytracer::tracer::{AmbientOcclusion, ImageBasedLighting};
+
+use demo::DemoScene;
+use options::{FrameRange, Options};
+use progress::Progress;
 
 const SCALE: usize = 4;
 const IMAGE_WIDTH: usize = 512 * SCALE;
 const IMAGE_HEIGHT_HALF: usize = 256 * SCALE;
 const IMAGE_HEIGHT: usize = IMAGE_HEIGHT_HALF * 2;
 
-fn set_color(pixel: &mut [u8], col: &Vec3) {
-    pixel[0] = (col.x * 255.0) as u8;
-    pixel[1] = (col.y * 255.0) as u8;
-    pixel[2] = (col.z * 255.0) as u8;
-    pixel[3] = 255;
+/// Decides when intermediate images or checkpoints are written during a progressive render.
+struct Periodic {
+    interval: Option<std::time::Duration>,
+    every_passes: Option<usize>,
+    last: Mutex<Instant>,
 }
 
-fn _ambient_occlusion(
-    objects: &[Box<SceneObject>],
-    pos: &Vec3,
-    normal: &Vec3,
-    rng: &mut dyn rand::RngCore,
-    num_samples: u32,
-    distance_cutoff: f64,
-) -> f64 {
-    let mut num_hits = 0;
-    for _ in 0..num_samples {
-        let d = normal.get_cosine_distributed_random_ray(rng);
-        if let Some(hit) = trace_ray(objects, pos, &d) {
-            if hit.distance < distance_cutoff {
-                num_hits += 1;
-            }
+impl Periodic {
+    fn new(interval: Option<std::time::Duration>, every_passes: Option<usize>) -> Periodic {
+        Periodic {
+            interval,
+            every_passes,
+            last: Mutex::new(Instant::now()),
         }
     }
 
-    1.0 - (num_hits as f64) / (num_samples as f64)
-}
+    /// Returns true if the interval has elapsed, at most once per interval.
+    fn time_due(&self) -> bool {
+        let mut last = self.last.lock().unwrap();
+        let due = self
+            .interval
+            .is_some_and(|interval| last.elapsed() >= interval);
+        if due {
+            *last = Instant::now();
+        }
+        due
+    }
 
-fn trace_ray<'a>(
-    objects: &'a [Box<SceneObject>],
-    ray_src: &Vec3,
-    ray_dir: &Vec3,
-) -> Option<scene_objects::HitRecord<'a>> {
-    //    println!("tracing ray from {} with {}", ray_src, ray_dir);
-    let mut hit_obj: Option<scene_objects::HitRecord> = None;
-
-    for obj in objects.iter() {
-        if let Some(hit) = obj.hit(ray_src, ray_dir) {
-            //println!("\tHit obj {} at {}", hit.object.get_material().color, (ray_src + ray_dir*hit.distance));
-            if let Some(ref mut best_hit) = hit_obj {
-                if hit.distance < best_hit.distance {
-                    *best_hit = hit;
-                }
-            } else {
-                hit_obj = Some(hit)
-            }
+    /// Returns true if `completed_passes` is a multiple of the pass interval.
+    fn pass_due(&self, completed_passes: usize) -> bool {
+        let due = self
+            .every_passes
+            .is_some_and(|every| completed_passes.is_multiple_of(every));
+        if due {
+            *self.last.lock().unwrap() = Instant::now();
         }
+        due
     }
+}
 
-    hit_obj
+/// Shows the progress of the render and periodically writes snapshots and checkpoints.
+struct CliObserver<'a> {
+    progress: Progress,
+    output: &'a Path,
+    snapshots: Periodic,
+    /// Held while a snapshot is written, as timed and per-pass snapshots may be due on
+    /// different workers at once and would write the same temporary file
+    snapshot_lock: Mutex<()>,
+    checkpoints: Periodic,
+    write_checkpoint: &'a (dyn Fn(usize, &Framebuffer) + Sync),
+    interrupted: Arc<AtomicBool>,
+    denoise: Option<&'a DenoiseSettings>,
 }
 
-fn get_color(
-    objects: &Vec<Box<SceneObject>>,
-    ray_src: &Vec3,
-    ray_dir: &Vec3,
-    light_dir: &Vec3,
-    _rng: &mut dyn rand::RngCore,
-    recursion_depth: u32,
-) -> Vec3 {
-    if recursion_depth > 5 {
-        return Vec3::new(0.5, 0.5, 0.5);
+impl CliObserver<'_> {
+    fn write_snapshot(&self, framebuffer: &Mutex<Framebuffer>) {
+        let _writing = self.snapshot_lock.lock().unwrap();
+        let rgba = resolve_image(framebuffer, self.denoise);
+        stats::time(Phase::Encode, || {
+            framebuffer::save_png(self.output, IMAGE_WIDTH, IMAGE_HEIGHT, &rgba)
+        })
+        .expect("Failed to write snapshot");
     }
+}
 
-    let ambient = 0.1;
-
-    if let Some(obj) = trace_ray(objects, ray_src, ray_dir) {
-        //return Vec3::new(1.0, 1.0, 1.0) * obj.distance * 0.001;
-        let p_hit = ray_src + ray_dir * obj.distance;
-        let n = obj.normal;
-        let p_hit = p_hit + n * 1E-7;
-
-        let diffuse = clamp(Vec3::dot(n, *light_dir), 0.0, 1.0);
-
-        let material = &obj.object.get_material();
-        let light_color = Vec3::new(1.0, 0.7, 0.8);
-        let r = ray_dir.reflect_at(&n);
-        let specular = clamp(Vec3::dot(r, *light_dir), 0.0, 1.0).powf(material.specular_exponent)
-            * material.specular_strength;
-
-        let brightness = diffuse + ambient;
-        /*if recursion_depth == 1 {
-            // Compute ambient occlusion only for the object hit by the camera ray and the first
-            // reflection, to save some computation time.
-            brightness *= ambient_occlusion(objects, &p_hit, &n, rng, 100, 200.0)
-        } ;*/
-        let color = material.color * brightness + light_color * specular;
-        if material.reflectance > 0.0 {
-            color * (1.0 - material.reflectance)
-                + get_color(
-                    objects,
-                    &p_hit,
-                    &ray_dir.reflect_at(&n),
-                    light_dir,
-                    _rng,
-                    recursion_depth + 1,
-                ) * material.reflectance
-        } else {
-            color
+impl RenderObserver for CliObserver<'_> {
+    fn render_started(&self, num_tiles: usize) {
+        self.progress.set_total(num_tiles);
+    }
+
+    fn tile_done(&self, pass: usize, rays: u64, framebuffer: &Mutex<Framebuffer>) {
+        self.progress.tile_done(rays);
+        if self.snapshots.time_due() {
+            self.write_snapshot(framebuffer);
+        }
+        if self.checkpoints.time_due() {
+            // Written from a copy, so that the workers are not blocked while the file is written
+            let copy = framebuffer.lock().unwrap().clone();
+            (self.write_checkpoint)(pass, &copy);
         }
-    } else {
-        Vec3::new(0.0, 0.3, 0.8)
     }
-}
 
-fn clamp(v: f64, min: f64, max: f64) -> f64 {
-    if v < min {
-        return min;
+    fn pass_done(&self, pass: usize, framebuffer: &Mutex<Framebuffer>) {
+        if self.snapshots.pass_due(pass + 1) {
+            self.write_snapshot(framebuffer);
+        }
     }
 
-    if v > max {
-        return max;
+    fn should_stop(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
     }
+}
 
-    v
+/// Converts the framebuffer to the final 8-bit image, denoising it first if requested. Only
+/// copying the buffers happens under the lock, so that the filter does not block the workers.
+fn resolve_image(framebuffer: &Mutex<Framebuffer>, denoise: Option<&DenoiseSettings>) -> Vec<u8> {
+    let Some(settings) = denoise else {
+        return framebuffer.lock().unwrap().to_rgba8();
+    };
+    let input = DenoiseInput::from_framebuffer(&framebuffer.lock().unwrap())
+        .expect("The denoiser's guide AOVs are rendered");
+    let colors = stats::time(Phase::Denoise, || input.denoise(settings));
+    framebuffer::tone_map(&colors)
 }
 
-fn create_scene() -> Vec<Box<SceneObject>> {
-    let mut objects: Vec<Box<SceneObject>> = vec![
-        Box::new(scene_objects::Sphere::new(
-            Vec3::new(-100.0, -80.0, 400.0),
-            40.0,
-            scene_objects::Material::new_diffuse(Vec3::new(0.8, 0.8, 0.8)),
-        )),
-        Box::new(scene_objects::Sphere::new(
-            Vec3::new(100.0, -80.0, 400.0),
-            40.0,
-            scene_objects::Material::new_diffuse(Vec3::new(0.8, 0.8, 0.8)),
-        )),
-        /*  Box::new(scene_objects::Sphere::new(
-            Vec3::new(0.0, 50.0, 700.0),
-            350.0,
-            scene_objects::Material::new(Vec3::new(0.8, 0.8, 0.0), 0.1, 1.0, 10.0),
-        )),*/
-        Box::new(scene_objects::Sphere::new(
-            Vec3::new(100.0, -80.0, 370.0),
-            20.0,
-            scene_objects::Material::new(Vec3::new(0.1, 0.1, 0.1), 0.0, 1.0, 20.0),
-        )),
-        Box::new(scene_objects::Sphere::new(
-            Vec3::new(-100.0, -80.0, 370.0),
-            20.0,
-            scene_objects::Material::new(Vec3::new(0.1, 0.1, 0.1), 0.0, 1.0, 20.0),
-        )),
-        Box::new(scene_objects::Plane::new(
-            Vec3::new(0.0, 200.0, 0.0),
-            Vec3::new(0.0, -1.0, 0.0),
-            scene_objects::Material::new_diffuse(Vec3::new(0.1, 0.5, 0.1)),
-        )),
-    ];
-
-    use rand::SeedableRng;
-    let mut rng = rand::rngs::SmallRng::seed_from_u64(44);
-    use crate::rand::Rng;
-    for _i in 0..800 {
-        let center = Vec3::new(
-            (rng.gen::<f64>() - 0.5) * 4000.0,
-            200.0 - (rng.gen::<f64>()) * 2000.0,
-            (rng.gen::<f64>() - 0.5) * 4000.0,
-        );
+/// Returns the file an AOV is written to: next to the image, e.g. `image.depth.pfm` for
+/// `image.png`.
+fn aov_path(output: &Path, aov: Aov) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{stem}.{}.pfm", aov.name()))
+}
 
-        let dist = center.len();
-        objects.push(Box::new(scene_objects::Sphere::new(
-            center,
-            rng.gen::<f64>() * dist / 5.0,
-            Material::new(
-                Vec3::new(
-                    0.5 + 0.5 * rng.gen::<f64>(),
-                    0.5 + 0.5 * rng.gen::<f64>(),
-                    0.5 + 0.5 * rng.gen::<f64>(),
-                ),
-                0.3,
-                1.0,
-                50.0,
-            ),
-        )));
-    }
+/// Returns the file a frame of an animation is written to: the run of '#' characters in
+/// `pattern` is replaced by the frame number, padded with zeros to the same length.
+fn frame_path(pattern: &str, frame: u32) -> PathBuf {
+    let start = pattern.find('#').expect("The output pattern contains '#'");
+    let len = pattern[start..].chars().take_while(|&c| c == '#').count();
+    let number = format!("{frame:0len$}");
+    PathBuf::from(format!(
+        "{}{number}{}",
+        &pattern[..start],
+        &pattern[start + len..]
+    ))
+}
 
-    objects.push(Box::new(
-        TriangleMesh::from_obj_file(
-            "data/bunny.obj",
-            Material::new(Vec3::new(0.8, 0.2, 0.2), 0.0, 0.3, 32.0),
+fn main() {
+    let options = Options::from_args();
+    let demo = stats::time(Phase::SceneBuild, DemoScene::new);
+    // Loaded once for all frames
+    let environment_map = options.environment.as_ref().map(|path| {
+        let map = stats::time(Phase::SceneBuild, || EnvironmentMap::load(Path::new(path)))
+            .expect("Failed to load environment map");
+        Arc::new(
+            map.with_rotation(options.environment_rotation.to_radians())
+                .with_intensity(options.environment_intensity),
         )
-        .expect("Valid OBJ"),
-    ));
+    });
 
-    objects
-}
+    let interrupted = Arc::new(AtomicBool::new(false));
+    if options.checkpoint.is_some() || options.resume.is_some() {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || {
+            if interrupted.swap(true, Ordering::Relaxed) {
+                // Interrupted a second time, give up on writing the checkpoint
+                std::process::exit(130);
+            }
+        })
+        .expect("Failed to install the Ctrl-C handler");
+    }
 
-fn _trace_line_360_sbs(row: &mut [u8], row_idx: usize, objects: &Vec<Box<SceneObject>>) {
-    println!("Tracing line {row_idx}");
-    let ray_src = Vec3::new(0.0, 0.0, 0.0);
-    let light_dir = Vec3::new(-1.0, -1.0, -1.0).normalized();
-
-    use rand::SeedableRng;
-    let mut rng = rand::rngs::SmallRng::from_entropy();
-    let y_rel = ((row_idx % IMAGE_HEIGHT_HALF) as f64) / (IMAGE_HEIGHT_HALF as f64);
-    let top = row_idx < IMAGE_HEIGHT_HALF;
-    let y_rad = (y_rel - 0.5) * std::f64::consts::PI;
-
-    for x in 0..IMAGE_WIDTH {
-        let x_rel = (x as f64) / (IMAGE_WIDTH as f64);
-        let x_rad = (x_rel - 0.5) * 2.0 * std::f64::consts::PI;
-
-        let v = Vec3::new(
-            x_rad.sin() * y_rad.cos(),
-            y_rad.sin(),
-            x_rad.cos() * y_rad.cos(),
-        )
-        .normalized();
+    let mut threads = Vec::new();
+    match options.frames {
+        None => render_image(
+            &options,
+            &demo,
+            environment_map.as_ref(),
+            0.0,
+            Path::new(&options.output),
+            &interrupted,
+            &mut threads,
+        ),
+        Some(range) => {
+            let (first, last) = match range {
+                FrameRange::All => (1, (demo::DURATION * options.fps).round() as u32 + 1),
+                FrameRange::Range(first, last) => (first, last),
+            };
+            for frame in first..=last {
+                if !options.quiet {
+                    eprintln!("Frame {frame} ({first}-{last})");
+                }
+                // Frame 1 shows the scene at time zero
+                let time = (frame - 1) as f64 / options.fps;
+                let output = frame_path(&options.output, frame);
+                render_image(
+                    &options,
+                    &demo,
+                    environment_map.as_ref(),
+                    time,
+                    &output,
+                    &interrupted,
+                    &mut threads,
+                );
+            }
+        }
+    }
 
-        let ray_src = ray_src + Vec3::new(if top { -10.0 } else { 10.0 }, 0.0, 0.0);
+    let mut total = stats::take_thread_stats();
+    for thread in &threads {
+        total.merge(thread);
+    }
+    let report = stats::Report { total, threads };
 
-        let col = get_color(objects, &ray_src, &v, &light_dir, &mut rng, 0);
-        // Transform colors from physical to perceptual
-        let col = Vec3::new(
-            clamp(col.x, 0.0, 1.0).sqrt(),
-            clamp(col.y, 0.0, 1.0).sqrt(),
-            clamp(col.z, 0.0, 1.0).sqrt(),
-        );
-        set_color(&mut row[(x * 4)..], &col);
+    if options.stats {
+        eprint!("{}", report.to_text());
+    }
+    if let Some(path) = &options.stats_json {
+        std::fs::write(path, report.to_json()).expect("Failed to write statistics");
     }
 }
 
-fn _trace_line(row: &mut [u8], row_idx: usize, objects: &Vec<Box<SceneObject>>) {
-    println!("Tracing line {row_idx}");
-    let ray_src = Vec3::new(0.0, 0.0, 0.0);
-    let light_dir = Vec3::new(-1.0, -1.0, -1.0).normalized();
-
-    use rand::SeedableRng;
-    let mut rng = rand::rngs::SmallRng::from_entropy();
+/// Describes the options that the samples of a render depend on, except for those stored in
+/// checkpoints separately, one per line. A checkpoint can only be resumed with the same ones.
+fn sample_options(options: &Options) -> String {
+    let Options {
+        projection,
+        aperture_radius,
+        focal_distance,
+        aperture_shape,
+        fps,
+        shutter,
+        passes,
+        ao_samples,
+        ao_distance,
+        ao_all_depths,
+        ao_only,
+        environment,
+        environment_rotation,
+        environment_intensity,
+        sky,
+        turbidity,
+        ibl_samples,
+        fog,
+        ..
+    } = options;
+    [
+        format!("projection {projection:?}"),
+        format!("aperture {aperture_radius:?}"),
+        format!("focal distance {focal_distance}"),
+        format!("aperture shape {aperture_shape:?}"),
+        format!("fps {fps}"),
+        format!("shutter {shutter}"),
+        format!("passes {passes}"),
+        format!("ao samples {ao_samples:?}"),
+        format!("ao distance {ao_distance}"),
+        format!("ao at all depths {ao_all_depths}"),
+        format!("ao only {ao_only}"),
+        format!("environment {environment:?}"),
+        format!("environment rotation {environment_rotation}"),
+        format!("environment intensity {environment_intensity}"),
+        format!("sky {sky}"),
+        format!("turbidity {turbidity}"),
+        format!("ibl samples {ibl_samples:?}"),
+        format!("fog {fog:?}"),
+    ]
+    .join("\n")
+}
 
-    for x in 0..IMAGE_WIDTH {
-        let v = Vec3::new(
-            x as f64 - (IMAGE_WIDTH as f64 / 2.0),
-            row_idx as f64 - IMAGE_HEIGHT_HALF as f64,
-            IMAGE_WIDTH as f64 / 2.0,
-        )
-        .normalized();
-
-        let col = get_color(objects, &ray_src, &v, &light_dir, &mut rng, 0);
-        // Transform colors from physical to perceptual
-        let col = Vec3::new(
-            clamp(col.x, 0.0, 1.0).sqrt(),
-            clamp(col.y, 0.0, 1.0).sqrt(),
-            clamp(col.z, 0.0, 1.0).sqrt(),
-        );
-        set_color(&mut row[(x * 4)..], &col);
+/// Renders the demo scene at `time` and writes the image and its AOVs. The statistics of the
+/// worker threads are added to `threads`.
+fn render_image(
+    options: &Options,
+    demo: &DemoScene,
+    environment_map: Option<&Arc<EnvironmentMap>>,
+    time: f64,
+    output: &Path,
+    interrupted: &Arc<AtomicBool>,
+    threads: &mut Vec<Stats>,
+) {
+    let mut scene = stats::time(Phase::SceneBuild, || demo.scene(time));
+    if let Some(map) = environment_map {
+        scene.environment = Environment::Map(map.clone());
+    } else if options.sky {
+        // The sun follows the light
+        let sky = stats::time(Phase::SceneBuild, || {
+            Sky::new(
+                scene.light_dir,
+                options.turbidity,
+                options.environment_intensity,
+            )
+        });
+        scene.environment = Environment::Sky(Arc::new(sky));
     }
-}
+    scene.fog = options.fog.map(Fog::new);
+    let mut camera = Camera::new(options.projection, IMAGE_WIDTH, IMAGE_HEIGHT);
+    camera.position = demo.camera_position(time);
+    camera.shutter = (time, time + options.shutter / options.fps);
+    camera.lens = options.aperture_radius.map(|aperture_radius| ThinLens {
+        aperture_radius,
+        focal_distance: options.focal_distance,
+        aperture_shape: options.aperture_shape,
+    });
 
-fn main() {
-    // For reading and opening files
-    use std::fs::File;
-    use std::io::BufWriter;
-    use std::path::Path;
-
-    let objects = create_scene();
-    let mut image_data: Vec<u8> = vec![0; IMAGE_WIDTH * IMAGE_HEIGHT * 4];
-    let tasks: std::collections::LinkedList<(usize, &mut [u8])> =
-        image_data.chunks_mut(IMAGE_WIDTH * 4).enumerate().collect();
-    let shared_tasks = Arc::new(Mutex::new(tasks));
-
-    std::thread::scope(|scope| {
-        // create as many worker threads as we have CPU cores
-        for _ in 1..=num_cpus::get() {
-            let shared_tasks_clone = shared_tasks.clone();
-
-            scope.spawn(|| {
-                let shared_tasks_clone = shared_tasks_clone;
-                let take_one = || shared_tasks_clone.lock().unwrap().pop_front();
-
-                while let Some((idx, row)) = take_one() {
-                    _trace_line_360_sbs(row, idx, &objects)
-                }
-            });
+    let mut settings = RenderSettings {
+        passes: options.passes,
+        seed: options.seed,
+        threads: options.threads,
+        tile_size: options.tile_size,
+        tile_order: options.tile_order,
+        ambient_occlusion: options.ao_samples.map(|samples| AmbientOcclusion {
+            samples,
+            max_distance: options.ao_distance,
+            all_depths: options.ao_all_depths,
+        }),
+        image_based_lighting: options
+            .ibl_samples
+            .map(|samples| ImageBasedLighting { samples }),
+        mode: if options.ao_only {
+            RenderMode::AmbientOcclusion
+        } else {
+            RenderMode::Beauty
+        },
+        aovs: options.aovs.clone(),
+    };
+    let denoise_settings = options
+        .denoise_iterations
+        .map(|iterations| DenoiseSettings {
+            iterations,
+            threads: options.threads,
+            ..DenoiseSettings::default()
+        });
+    if denoise_settings.is_some() {
+        for aov in denoise::GUIDE_AOVS {
+            if !settings.aovs.contains(&aov) {
+                settings.aovs.push(aov);
+            }
+        }
+    }
+    let sample_options = sample_options(options);
+    let (framebuffer, first_pass) = match &options.resume {
+        Some(path) => {
+            let checkpoint = checkpoint::load(Path::new(path)).expect("Failed to load checkpoint");
+            let fb = &checkpoint.framebuffer;
+            if fb.width() != IMAGE_WIDTH || fb.height() != IMAGE_HEIGHT {
+                eprintln!(
+                    "Error: the checkpoint has a size of {}x{} pixels, expected {IMAGE_WIDTH}x{IMAGE_HEIGHT}",
+                    fb.width(),
+                    fb.height()
+                );
+                std::process::exit(1);
+            }
+            if denoise_settings.is_some()
+                && !denoise::GUIDE_AOVS
+                    .iter()
+                    .all(|aov| fb.aovs().contains(aov))
+            {
+                eprintln!(
+                    "Error: the checkpoint was rendered without the AOVs that guide the denoiser, resume it without --denoise"
+                );
+                std::process::exit(1);
+            }
+            if checkpoint.options != sample_options {
+                let current: Vec<&str> = sample_options.lines().collect();
+                let differing: Vec<&str> = checkpoint
+                    .options
+                    .lines()
+                    .filter(|line| !current.contains(line))
+                    .collect();
+                eprintln!(
+                    "Error: the checkpoint was rendered with different options: {}",
+                    differing.join(", ")
+                );
+                std::process::exit(1);
+            }
+            settings.seed = checkpoint.seed;
+            settings.tile_size = checkpoint.tile_size;
+            settings.tile_order = checkpoint.tile_order;
+            settings.aovs = fb.aovs().to_vec();
+            (checkpoint.framebuffer, checkpoint.pass)
+        }
+        None => (
+            Framebuffer::with_aovs(IMAGE_WIDTH, IMAGE_HEIGHT, &settings.aovs),
+            0,
+        ),
+    };
+
+    let checkpoint_path = options.checkpoint.as_ref().or(options.resume.as_ref());
+    let write_checkpoint = |pass: usize, framebuffer: &Framebuffer| {
+        if let Some(path) = checkpoint_path {
+            checkpoint::save(
+                Path::new(path),
+                settings.seed,
+                pass,
+                settings.tile_size,
+                settings.tile_order,
+                &sample_options,
+                framebuffer,
+            )
+            .expect("Failed to write checkpoint");
         }
+    };
+
+    let observer = CliObserver {
+        progress: Progress::new(options.quiet),
+        output,
+        snapshots: Periodic::new(options.snapshot_interval, options.snapshot_passes),
+        snapshot_lock: Mutex::new(()),
+        checkpoints: Periodic::new(options.checkpoint_interval, None),
+        write_checkpoint: &write_checkpoint,
+        interrupted: interrupted.clone(),
+        denoise: denoise_settings.as_ref(),
+    };
+
+    let outcome = stats::time(Phase::Render, || {
+        render::render_from(
+            &scene,
+            &camera,
+            &settings,
+            framebuffer,
+            first_pass,
+            &observer,
+        )
     });
+    observer.progress.finish();
 
-    let path = Path::new(r"image.png");
-    let file = File::create(path).unwrap();
-    let w = BufWriter::new(file);
+    if let Some(pass) = outcome.stopped_in_pass {
+        write_checkpoint(pass, &outcome.framebuffer);
+        eprintln!(
+            "Render interrupted, resume it with '--resume {}'",
+            checkpoint_path.unwrap()
+        );
+    } else if let Some(path) = checkpoint_path {
+        // The render is complete, so the checkpoint is not needed anymore
+        let _ = std::fs::remove_file(path);
+    }
 
-    let mut encoder = png::Encoder::new(w, IMAGE_WIDTH as u32, IMAGE_HEIGHT as u32);
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    let mut writer = encoder.write_header().unwrap();
-    writer.write_image_data(&image_data).unwrap(); // Save
+    let framebuffer = Mutex::new(outcome.framebuffer);
+    let rgba = resolve_image(&framebuffer, denoise_settings.as_ref());
+    let framebuffer = framebuffer.into_inner().unwrap();
+    stats::time(Phase::Encode, || {
+        framebuffer::save_png(output, IMAGE_WIDTH, IMAGE_HEIGHT, &rgba)
+    })
+    .expect("Failed to write image");
+
+    for &aov in framebuffer.aovs() {
+        let values = framebuffer.aov(aov).unwrap();
+        let path = aov_path(output, aov);
+        stats::time(Phase::Encode, || {
+            framebuffer::save_pfm(&path, IMAGE_WIDTH, IMAGE_HEIGHT, &values)
+        })
+        .expect("Failed to write AOV");
+    }
+
+    // Threads of consecutive frames are merged, as they run one after another
+    for (i, thread) in outcome.thread_stats.iter().enumerate() {
+        match threads.get_mut(i) {
+            Some(total) => total.merge(thread),
+            None => threads.push(*thread),
+        }
+    }
 }
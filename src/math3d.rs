@@ -1,5 +1,12 @@
 extern crate rand;
 
+/// Bound on the relative rounding error of `n` floating point operations, see
+/// https://pbr-book.org/3ed-2018/Shapes/Managing_Rounding_Error
+pub(crate) fn gamma(n: u32) -> f64 {
+    let e = n as f64 * f64::EPSILON * 0.5;
+    e / (1.0 - e)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Axis {
     X,
@@ -44,7 +51,7 @@ impl ops::Add<Vec3> for Vec3 {
     }
 }
 
-impl<'a> ops::Add<Vec3> for &'a Vec3 {
+impl ops::Add<Vec3> for &Vec3 {
     type Output = Vec3;
 
     fn add(self, rhs: Vec3) -> Vec3 {
@@ -52,7 +59,7 @@ impl<'a> ops::Add<Vec3> for &'a Vec3 {
     }
 }
 
-impl<'a> ops::Neg for &'a Vec3 {
+impl ops::Neg for &Vec3 {
     type Output = Vec3;
 
     fn neg(self) -> Vec3 {
@@ -68,7 +75,7 @@ impl ops::Sub<Vec3> for Vec3 {
     }
 }
 
-impl<'a, 'b> ops::Sub<&'a Vec3> for &'b Vec3 {
+impl<'a> ops::Sub<&'a Vec3> for &Vec3 {
     type Output = Vec3;
 
     fn sub(self, rhs: &'a Vec3) -> Vec3 {
@@ -84,7 +91,7 @@ impl ops::Mul<f64> for Vec3 {
     }
 }
 
-impl<'a> ops::Mul<f64> for &'a Vec3 {
+impl ops::Mul<f64> for &Vec3 {
     type Output = Vec3;
 
     fn mul(self, rhs: f64) -> Vec3 {
@@ -135,22 +142,24 @@ impl Vec3 {
         )
     }
 
+    /// Multiplies the components of both vectors, e.g. to filter light by a color.
+    pub fn mul_elementwise(&self, rhs: &Vec3) -> Vec3 {
+        Vec3::new(self.x * rhs.x, self.y * rhs.y, self.z * rhs.z)
+    }
+
     pub fn reflect_at(&self, normal: &Vec3) -> Vec3 {
         //copied from the internet, not verified
         self - &(normal * normal.dot(*self) * 2.0)
     }
 
     pub fn get_cosine_distributed_random_ray(&self, rng: &mut dyn rand::RngCore) -> Vec3 {
-        // Step 1:Compute a uniformly distributed point on the unit disk
         use crate::rand::Rng;
-        let r = f64::sqrt(rng.gen::<f64>());
-        let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
-
-        // Step 2: Project point onto unit hemisphere
-        let u = r * f64::cos(phi);
-        let v = r * f64::sin(phi);
-        let n = f64::sqrt(1.0 - r * r);
+        self.get_cosine_distributed_ray(rng.gen::<f64>(), rng.gen::<f64>())
+    }
 
+    /// Returns two unit vectors that are perpendicular to `self`, which must be normalized, and
+    /// to each other.
+    pub fn perpendicular_axes(&self) -> (Vec3, Vec3) {
         let udir = if f64::abs(Vec3::new(0.0, 0.0, 1.0).dot(*self)) >= 0.999999 {
             //are (nearly) colinear --> cannot build coordinate base
             Vec3::new(0.0, 1.0, 0.0)
@@ -160,8 +169,174 @@ impl Vec3 {
 
         let vdir = udir.cross(*self).normalized();
         let udir = vdir.cross(*self).normalized();
+        (udir, vdir)
+    }
+
+    /// Maps a point (`u1`, `u2`) of the unit square to a direction on the hemisphere around
+    /// `self`, so that uniformly distributed points yield cosine-distributed directions.
+    /// Stratified points on the square therefore give stratified directions.
+    pub fn get_cosine_distributed_ray(&self, u1: f64, u2: f64) -> Vec3 {
+        // Step 1:Compute a uniformly distributed point on the unit disk
+        let r = f64::sqrt(u1);
+        let phi = 2.0 * std::f64::consts::PI * u2;
+
+        // Step 2: Project point onto unit hemisphere
+        let u = r * f64::cos(phi);
+        let v = r * f64::sin(phi);
+        let n = f64::sqrt(1.0 - r * r);
+
+        let (udir, vdir) = self.perpendicular_axes();
 
         //# Convert to a direction on the hemisphere defined by the normal
         udir * u + vdir * v + self * n
     }
 }
+
+/// A rotation, represented by a unit quaternion.
+#[derive(Debug, Copy, Clone)]
+pub struct Rotation {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Rotation {
+    pub fn identity() -> Rotation {
+        Rotation {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    /// Rotation by `angle` (in radians) around `axis`
+    pub fn from_axis_angle(axis: Vec3, angle: f64) -> Rotation {
+        let axis = axis.normalized() * (angle / 2.0).sin();
+        Rotation {
+            w: (angle / 2.0).cos(),
+            x: axis.x,
+            y: axis.y,
+            z: axis.z,
+        }
+    }
+
+    pub fn inverse(&self) -> Rotation {
+        Rotation {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        // v + 2w(q × v) + 2q × (q × v), with q the vector part of the quaternion
+        let q = Vec3::new(self.x, self.y, self.z);
+        let t = q.cross(v) * 2.0;
+        v + t * self.w + q.cross(t)
+    }
+
+    /// Weighted sum of four rotations, normalized. Interpolates smoothly between rotations that
+    /// are not too far apart, like the keyframes of an animation.
+    pub fn blend(rotations: [&Rotation; 4], weights: [f64; 4]) -> Rotation {
+        let reference = rotations[1];
+        let mut sum = Rotation {
+            w: 0.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        for (r, weight) in rotations.into_iter().zip(weights) {
+            // q and -q are the same rotation, take the one that is closer to the reference
+            let dot = r.w * reference.w + r.x * reference.x + r.y * reference.y + r.z * reference.z;
+            let weight = if dot < 0.0 { -weight } else { weight };
+            sum.w += r.w * weight;
+            sum.x += r.x * weight;
+            sum.y += r.y * weight;
+            sum.z += r.z * weight;
+        }
+        sum.normalized()
+    }
+
+    fn normalized(&self) -> Rotation {
+        let len = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Rotation {
+            w: self.w / len,
+            x: self.x / len,
+            y: self.y / len,
+            z: self.z / len,
+        }
+    }
+
+    /// Spherical linear interpolation, rotating at constant speed along the shortest path
+    pub fn slerp(&self, other: &Rotation, t: f64) -> Rotation {
+        let mut cos = self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z;
+        // q and -q are the same rotation, take the one that is closer
+        let other = if cos < 0.0 {
+            cos = -cos;
+            Rotation {
+                w: -other.w,
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+            }
+        } else {
+            *other
+        };
+
+        let (a, b) = if cos > 0.9995 {
+            // Nearly identical, linear interpolation is precise enough and avoids dividing by ~0
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Rotation {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        }
+        .normalized()
+    }
+}
+
+/// A similarity transform: uniform scaling, followed by a rotation and a translation. Since the
+/// scaling is uniform, normals are transformed just like directions.
+#[derive(Debug, Copy, Clone)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Rotation,
+    pub scale: f64,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Rotation::identity(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        self.rotation.rotate(p * self.scale) + self.translation
+    }
+
+    pub fn transform_direction(&self, d: Vec3) -> Vec3 {
+        self.rotation.rotate(d * self.scale)
+    }
+
+    /// Maps a point from the transformed space back into the original space.
+    pub fn inverse_transform_point(&self, p: Vec3) -> Vec3 {
+        self.rotation.inverse().rotate(p - self.translation) * (1.0 / self.scale)
+    }
+
+    /// Maps a direction from the transformed space back into the original space.
+    pub fn inverse_transform_direction(&self, d: Vec3) -> Vec3 {
+        self.rotation.inverse().rotate(d) * (1.0 / self.scale)
+    }
+}
@@ -0,0 +1,265 @@
+//! Participating media, which absorb and scatter light along rays instead of only at surfaces:
+//! fog filling the whole scene and volumes, like smoke or clouds, bounded by closed objects.
+
+use std::f64::consts::PI;
+
+use crate::math3d::Vec3;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::scene_objects::{Object3D, SceneObject};
+use crate::stats::{self, Counter};
+use crate::tracer::{trace_any, AMBIENT};
+
+/// The Henyey-Greenstein phase function: the fraction of scattered light that leaves at an angle
+/// with cosine `cos_theta` to its original direction, per solid angle. `g` is the mean cosine,
+/// between -1 (backward scattering) and 1 (forward scattering), 0 scatters uniformly.
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+/// Radiance that a medium scatters towards the viewer, per unit of extinction. It scatters the
+/// fraction `light` of the directional light that arrives according to the phase function, and
+/// the ambient light uniformly.
+fn in_scattering(
+    scene: &Scene,
+    albedo: &Vec3,
+    anisotropy: f64,
+    ray_dir: &Vec3,
+    light: f64,
+) -> Vec3 {
+    // The light source has the irradiance π, so that it lights a white diffuse surface facing
+    // it with the radiance 1
+    let phase = henyey_greenstein(ray_dir.dot(scene.light_dir), anisotropy);
+    albedo * (phase * PI * light + AMBIENT)
+}
+
+/// Homogeneous fog filling the whole scene. It dims what is seen through it exponentially with
+/// the distance and adds the light it scatters towards the viewer. Rays leaving the scene
+/// travel through infinitely much fog, so the environment is hidden behind it.
+///
+/// Only the light along camera and reflected rays is affected, the fog does not cast shadows.
+#[derive(Debug, Clone)]
+pub struct Fog {
+    /// Extinction coefficient: the fraction of light that is absorbed or scattered per unit of
+    /// distance
+    pub density: f64,
+    /// Fraction of the extinguished light that is scattered rather than absorbed, per color
+    pub albedo: Vec3,
+    /// Mean cosine of the scattering angle, see `henyey_greenstein()`
+    pub anisotropy: f64,
+}
+
+impl Fog {
+    /// Light gray fog that scatters mostly forward
+    pub fn new(density: f64) -> Fog {
+        Fog {
+            density,
+            albedo: Vec3::new(0.9, 0.9, 0.9),
+            anisotropy: 0.3,
+        }
+    }
+}
+
+/// How dense a volume is.
+pub enum Density {
+    /// The same extinction coefficient everywhere
+    Homogeneous(f64),
+    /// An extinction coefficient that varies with the position
+    Heterogeneous(Box<dyn Fn(Vec3) -> f64 + Send + Sync>),
+}
+
+impl Density {
+    fn at(&self, p: Vec3) -> f64 {
+        match self {
+            Density::Homogeneous(density) => *density,
+            Density::Heterogeneous(density) => density(p).max(0.0),
+        }
+    }
+}
+
+/// A medium filling the inside of a closed object, like smoke or a cloud. The boundary itself is
+/// invisible. Volumes are rendered by ray marching: the part of a ray inside the boundary is
+/// divided into `steps` segments, at a random offset per ray, and each segment scatters the
+/// light arriving at a point within it. That light is shadowed by objects and by the volumes.
+///
+/// Volumes must not overlap each other, and their boundaries must be bounded, so not planes.
+pub struct Volume {
+    boundary: Box<SceneObject>,
+    density: Density,
+    albedo: Vec3,
+    anisotropy: f64,
+    steps: u32,
+}
+
+impl Volume {
+    pub fn new(
+        boundary: impl Object3D + Send + Sync + 'static,
+        density: Density,
+        albedo: Vec3,
+    ) -> Volume {
+        Volume {
+            boundary: Box::new(boundary),
+            density,
+            albedo,
+            anisotropy: 0.0,
+            steps: 32,
+        }
+    }
+
+    /// Mean cosine of the scattering angle, see `henyey_greenstein()`. The default scatters
+    /// uniformly.
+    pub fn with_anisotropy(self, anisotropy: f64) -> Volume {
+        Volume { anisotropy, ..self }
+    }
+
+    /// Number of ray marching steps through the volume. Heterogeneous volumes need more to
+    /// resolve their details. Shadow rays through the volume take a quarter of them.
+    pub fn with_steps(self, steps: u32) -> Volume {
+        Volume {
+            steps: steps.max(1),
+            ..self
+        }
+    }
+
+    /// Returns the parts of the ray inside the volume, clipped to `max_distance`.
+    fn segments(
+        &self,
+        ray_src: &Vec3,
+        ray_dir: &Vec3,
+        time: f64,
+        max_distance: f64,
+    ) -> Vec<(f64, f64)> {
+        self.boundary
+            .intervals(&Ray::new(*ray_src, *ray_dir, time))
+            .into_iter()
+            .map(|interval| {
+                (
+                    interval.enter.distance.max(0.0),
+                    interval.exit.distance.min(max_distance),
+                )
+            })
+            .filter(|(start, end)| start < end && end.is_finite())
+            .collect()
+    }
+
+    /// Returns the fraction of light that passes through the volume along the ray.
+    fn transmittance(&self, ray_src: &Vec3, ray_dir: &Vec3, time: f64) -> f64 {
+        let steps = (self.steps / 4).max(1);
+        let mut optical_depth = 0.0;
+        for (start, end) in self.segments(ray_src, ray_dir, time, f64::INFINITY) {
+            let step = (end - start) / steps as f64;
+            for i in 0..steps {
+                let p = ray_src + ray_dir * (start + (i as f64 + 0.5) * step);
+                optical_depth += self.density.at(p) * step;
+            }
+        }
+        (-optical_depth).exp()
+    }
+}
+
+/// Light scattered towards the viewer along a ray through media, and the fraction of the light
+/// from behind them that passes through, accumulated from front to back.
+struct Accumulator {
+    scattered: Vec3,
+    transmittance: f64,
+}
+
+impl Accumulator {
+    /// Adds a piece of the ray of `length` with a constant extinction coefficient `density` and
+    /// the in-scattered radiance `light` per unit of extinction. Within the piece, the integral
+    /// of the in-scattered light is exact.
+    fn add(&mut self, length: f64, density: f64, light: Vec3) {
+        if density <= 0.0 || length <= 0.0 {
+            return;
+        }
+        let transmittance = (-density * length).exp();
+        self.scattered = self.scattered + light * (self.transmittance * (1.0 - transmittance));
+        self.transmittance *= transmittance;
+    }
+}
+
+/// Returns the radiance arriving at `ray_src` from direction `ray_dir` through the media of the
+/// scene, given the `radiance` arriving from `distance` along the ray, where it hit a surface or
+/// left the scene. Without media, `radiance` is returned as is.
+pub fn attenuate(
+    scene: &Scene,
+    ray_src: &Vec3,
+    ray_dir: &Vec3,
+    time: f64,
+    distance: f64,
+    radiance: Vec3,
+    rng: &mut dyn rand::RngCore,
+) -> Vec3 {
+    use rand::Rng;
+
+    if scene.fog.is_none() && scene.volumes.is_empty() {
+        return radiance;
+    }
+
+    // Distances along the ray are in units of the length of `ray_dir`
+    let len = ray_dir.len();
+    let dir = ray_dir * (1.0 / len);
+    let (fog_density, fog_light) = match &scene.fog {
+        Some(fog) => (
+            fog.density,
+            in_scattering(scene, &fog.albedo, fog.anisotropy, &dir, 1.0),
+        ),
+        None => (0.0, Vec3::new(0.0, 0.0, 0.0)),
+    };
+
+    let mut segments: Vec<(&Volume, f64, f64)> = scene
+        .volumes
+        .iter()
+        .flat_map(|volume| {
+            volume
+                .segments(ray_src, ray_dir, time, distance)
+                .into_iter()
+                .map(move |(start, end)| (volume, start, end))
+        })
+        .collect();
+    segments.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    let mut accumulator = Accumulator {
+        scattered: Vec3::new(0.0, 0.0, 0.0),
+        transmittance: 1.0,
+    };
+    let mut marched = 0.0;
+    for (volume, start, end) in segments {
+        // Fog in front of the volume
+        accumulator.add((start - marched) * len, fog_density, fog_light);
+        marched = end;
+
+        let step = (end - start) / volume.steps as f64;
+        let offset = rng.gen::<f64>();
+        for i in 0..volume.steps {
+            let p = ray_src + ray_dir * (start + (i as f64 + offset) * step);
+            let density = volume.density.at(p);
+            if density <= 0.0 {
+                accumulator.add(step * len, fog_density, fog_light);
+                continue;
+            }
+
+            stats::count(Counter::ShadowRays, 1);
+            let light = if trace_any(&scene.objects, &Ray::new(p, scene.light_dir, time)) {
+                0.0
+            } else {
+                scene
+                    .volumes
+                    .iter()
+                    .map(|v| v.transmittance(&p, &scene.light_dir, time))
+                    .product()
+            };
+            let volume_light = in_scattering(scene, &volume.albedo, volume.anisotropy, &dir, light);
+
+            // The volume and the fog within it, weighted by their share of the extinction
+            let total_density = density + fog_density;
+            let light = (volume_light * density + fog_light * fog_density) * (1.0 / total_density);
+            accumulator.add(step * len, total_density, light);
+        }
+    }
+    // Fog behind the volumes. Rays leaving the scene travel through infinitely much of it.
+    accumulator.add((distance - marched) * len, fog_density, fog_light);
+
+    accumulator.scattered + radiance * accumulator.transmittance
+}
@@ -1,167 +1,881 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
 use crate::{
-    math3d::Vec3,
-    scene_objects::{HitRecord, Material, Object3D},
+    math3d::{gamma, Vec3},
+    mtl, ply,
+    ray::Ray,
+    scene_objects::{HitRecord, Interval, Material, Object3D},
+    simd::{Boxes4, Kernels, TriangleHit, Triangles4},
+    stats::{self, Counter, Phase},
+    stl,
+    texture::Texture,
 };
 
-pub struct Triangle<T> {
-    pub v1: T,
-    pub v2: T,
-    pub v3: T,
+/// A ray transformed so that it starts at the origin and points along the z axis, which the
+/// watertight triangle intersection works in. It is computed once per ray and used for all
+/// triangles.
+pub(crate) struct ShearedRay {
+    pub(crate) origin: Vec3,
+    /// Permutation of the axes that makes the largest component of the direction the z axis
+    pub(crate) axes: [usize; 3],
+    /// Shear of x and y, and scale of z
+    pub(crate) shear: [f64; 3],
+    pub(crate) direction_length: f64,
 }
 
-impl<T> Triangle<T> {
-    pub fn new(v1: T, v2: T, v3: T) -> Self {
-        Triangle { v1, v2, v3 }
+impl ShearedRay {
+    pub(crate) fn new(ray: &Ray) -> ShearedRay {
+        let direction = ray.direction();
+        let d = [direction.x, direction.y, direction.z];
+        let kz = (0..3)
+            .max_by(|&a, &b| d[a].abs().total_cmp(&d[b].abs()))
+            .unwrap();
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        // Keeps the winding of the triangles
+        if d[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+        ShearedRay {
+            origin: *ray.origin(),
+            axes: [kx, ky, kz],
+            shear: [-d[kx] / d[kz], -d[ky] / d[kz], 1.0 / d[kz]],
+            direction_length: direction.len(),
+        }
+    }
+
+    /// Transforms a vertex into the space of the ray. z is not scaled yet.
+    fn transform(&self, v: Vec3) -> [f64; 3] {
+        let p = v - self.origin;
+        let p = [p.x, p.y, p.z];
+        let [kx, ky, kz] = self.axes;
+        [
+            p[kx] + self.shear[0] * p[kz],
+            p[ky] + self.shear[1] * p[kz],
+            p[kz],
+        ]
     }
 }
 
-impl Triangle<Vec3> {
-    // Taken from https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
-    fn intersects(&self, ray_origin: Vec3, ray_direction: Vec3) -> Option<Vec3> {
-        let e1 = self.v2 - self.v1;
-        let e2 = self.v3 - self.v1;
+/// Intersects the line through the ray with the triangle with the `vertices`. Returns the
+/// distance along the ray, negative behind its origin, the barycentric coordinates of the hit
+/// with respect to the second and third vertex, and a bound on the rounding error of the
+/// distance.
+///
+/// The algorithm by Woop, Benthin and Wald (http://jcgt.org/published/0002/01/05/) is
+/// watertight: a ray that hits an edge or a vertex shared by several triangles hits at least
+/// one of them, so that rays do not leak through closed meshes.
+pub(crate) fn intersect_line(
+    vertices: [Vec3; 3],
+    ray: &ShearedRay,
+) -> Option<(f64, f64, f64, f64)> {
+    let [a, b, c] = vertices.map(|v| ray.transform(v));
 
-        let ray_cross_e2 = ray_direction.cross(e2);
-        let det = e1.dot(ray_cross_e2);
+    // Scaled barycentric coordinates, the signed areas of the triangles between the ray and the
+    // edges. They are computed in the same way for both triangles sharing an edge.
+    let e0 = b[0] * c[1] - b[1] * c[0];
+    let e1 = c[0] * a[1] - c[1] * a[0];
+    let e2 = a[0] * b[1] - a[1] * b[0];
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None; // This ray is parallel to this triangle.
+    }
 
-        if det > -f64::EPSILON && det < f64::EPSILON {
-            return None; // This ray is parallel to this triangle.
-        }
+    let [az, bz, cz] = [a[2], b[2], c[2]].map(|z| z * ray.shear[2]);
+    let inv_det = 1.0 / det;
+    let t = (e0 * az + e1 * bz + e2 * cz) * inv_det;
 
-        let inv_det = 1.0 / det;
-        let s = ray_origin - self.v1;
-        let u = inv_det * s.dot(ray_cross_e2);
-        if !(0.0..=1.0).contains(&u) {
-            return None;
-        }
+    // Bound the rounding error of t, relative to the magnitudes of the transformed coordinates
+    let max_abs = |values: [f64; 3]| values.into_iter().fold(0.0, |m: f64, v| m.max(v.abs()));
+    let max_x = max_abs([a[0], b[0], c[0]]);
+    let max_y = max_abs([a[1], b[1], c[1]]);
+    let max_z = max_abs([az, bz, cz]);
+    let max_e = max_abs([e0, e1, e2]);
+    let delta_x = gamma(5) * (max_x + max_z);
+    let delta_y = gamma(5) * (max_y + max_z);
+    let delta_z = gamma(3) * max_z;
+    let delta_e = 2.0 * (gamma(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+    let delta_t =
+        3.0 * (gamma(3) * max_e * max_z + delta_e * max_z + delta_z * max_e) * inv_det.abs();
 
-        let s_cross_e1 = s.cross(e1);
-        let v = inv_det * ray_direction.dot(s_cross_e1);
-        if v < 0.0 || u + v > 1.0 {
-            return None;
-        }
-        // At this stage we can compute t to find out where the intersection point is on the line.
-        let t = inv_det * e2.dot(s_cross_e1);
+    Some((
+        t * ray.direction_length,
+        e1 * inv_det,
+        e2 * inv_det,
+        delta_t * ray.direction_length,
+    ))
+}
 
-        if t > f64::EPSILON {
-            // ray intersection
-            Some(ray_origin + ray_direction * t)
-        } else {
-            // This means that there is a line intersection but not a ray intersection.
-            None
-        }
-    }
+/// Interpolates the `values` at the `vertices` of a triangle at the barycentric coordinates
+/// (`u`, `v`).
+fn interpolate(values: &[Vec3], vertices: &[u32; 3], u: f64, v: f64) -> Vec3 {
+    let [v1, v2, v3] = vertices.map(|i| values[i as usize]);
+    v1 * (1.0 - u - v) + v2 * u + v3 * v
 }
 
-pub fn parse_obj(filename: &str) -> std::io::Result<Vec<Triangle<Vec3>>> {
-    let mut vertices = Vec::new();
-    let mut faces = Vec::<(usize, usize, usize)>::new();
+/// Converts a vertex index to the type that meshes store them as.
+pub(crate) fn vertex_index(i: usize) -> std::io::Result<u32> {
+    u32::try_from(i).map_err(|_| invalid_data("Too many vertices for a mesh"))
+}
 
-    for l in std::fs::read_to_string(filename)?.lines() {
-        let line = l.trim();
-        if line.is_empty() {
-            // skip emtpy lines
-            continue;
-        }
+/// Indices of the position, texture coordinate and normal at a corner of an OBJ face
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Reads the triangles of an OBJ file with their vertex normals and texture coordinates, if all
+/// faces have them. Polygons with more than three vertices are split into triangles. The
+/// materials that `usemtl` assigns to the faces are read from the MTL files named by `mtllib`,
+/// relative to the OBJ file. Groups, objects and smoothing groups are ignored.
+pub fn parse_obj(path: &Path) -> std::io::Result<MeshData> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut positions = Vec::new();
+    let mut texcoords = Vec::new();
+    let mut normals = Vec::new();
+    // Corners with the same position, texture coordinate and normal become one vertex
+    let mut corners = Vec::<Corner>::new();
+    let mut vertices = HashMap::<Corner, u32>::new();
+    let mut triangles = Vec::<[u32; 3]>::new();
+    let mut library = HashMap::new();
+    let mut materials = Vec::new();
+    let mut material_indices = HashMap::new();
+    let mut current_material = None;
+    let mut face_materials = Vec::new();
 
-        if line.starts_with('#') {
-            // skip comment lines
+    for (line_number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
             continue;
         }
-
-        let mut parts = line.split(' ');
-        let mut next = || {
-            parts
-                .next()
-                .ok_or(std::io::Error::from(std::io::ErrorKind::Other))
+        let mut parts = line.split_ascii_whitespace();
+        let keyword = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.collect();
+        let invalid = || invalid_data(&format!("Invalid OBJ line {}: '{line}'", line_number + 1));
+        let number = |i: usize| -> std::io::Result<f64> {
+            args.get(i)
+                .and_then(|arg| arg.parse().ok())
+                .ok_or_else(invalid)
+        };
+        // Indices start at one, negative ones count back from the last element so far
+        let index = |arg: &str, count: usize| -> std::io::Result<usize> {
+            let i: i64 = arg.parse().map_err(|_| invalid())?;
+            let i = if i < 0 { count as i64 + i } else { i - 1 };
+            if (0..count as i64).contains(&i) {
+                Ok(i as usize)
+            } else {
+                Err(invalid())
+            }
         };
-        let indicator = next()?;
-        match indicator {
+
+        match keyword {
+            "v" => positions.push(Vec3::new(number(0)?, number(1)?, number(2)?)),
+            "vt" => texcoords.push((number(0)?, number(1).unwrap_or(0.0))),
+            "vn" => normals.push(Vec3::new(number(0)?, number(1)?, number(2)?)),
             "f" => {
-                faces.push((
-                    next()?.parse().expect("int"),
-                    next()?.parse().expect("int"),
-                    next()?.parse().expect("int"),
-                ));
+                // Each corner is "v", "v/vt", "v//vn" or "v/vt/vn"
+                let face = args
+                    .iter()
+                    .map(|arg| {
+                        let mut indices = arg.split('/');
+                        let position = index(indices.next().unwrap_or_default(), positions.len())?;
+                        let texcoord = match indices.next() {
+                            Some(i) if !i.is_empty() => Some(index(i, texcoords.len())?),
+                            _ => None,
+                        };
+                        let normal = match indices.next() {
+                            Some(i) => Some(index(i, normals.len())?),
+                            None => None,
+                        };
+                        let corner = (position, texcoord, normal);
+                        if let Some(&vertex) = vertices.get(&corner) {
+                            return Ok(vertex);
+                        }
+                        let vertex = vertex_index(corners.len())?;
+                        corners.push(corner);
+                        vertices.insert(corner, vertex);
+                        Ok(vertex)
+                    })
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                if face.len() < 3 {
+                    return Err(invalid());
+                }
+                for k in 1..face.len() - 1 {
+                    triangles.push([face[0], face[k], face[k + 1]]);
+                    face_materials.push(current_material);
+                }
+            }
+            "mtllib" => {
+                for file in &args {
+                    library.extend(mtl::parse_mtl(&dir.join(file))?);
+                }
             }
-            "v" => {
-                vertices.push(Vec3::new(
-                    next()?.parse().expect("f64"),
-                    next()?.parse().expect("f64"),
-                    next()?.parse().expect("f64"),
-                ));
+            "usemtl" => {
+                let name = args.join(" ");
+                current_material = Some(match material_indices.get(&name) {
+                    Some(&i) => i,
+                    None => {
+                        let material = library.remove(&name).ok_or_else(|| {
+                            invalid_data(&format!("Unknown material '{name}' in OBJ file"))
+                        })?;
+                        materials.push(material);
+                        material_indices.insert(name, materials.len() - 1);
+                        materials.len() - 1
+                    }
+                });
             }
-            _ => panic!("Unexpected indicator {}", indicator),
+            _ => {}
         }
-        if let Ok(el) = next() {
-            panic!("Found trailing element {el}");
+    }
+
+    // Normals and texture coordinates are used only if all corners have them
+    Ok(MeshData {
+        positions: corners.iter().map(|corner| positions[corner.0]).collect(),
+        normals: corners
+            .iter()
+            .map(|corner| Some(normals[corner.2?]))
+            .collect(),
+        colors: None,
+        texcoords: corners
+            .iter()
+            .map(|corner| Some(texcoords[corner.1?]))
+            .collect(),
+        triangles,
+        materials: (!materials.is_empty()).then_some(FaceMaterials {
+            materials,
+            indices: face_materials,
+        }),
+    })
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// A material of a mesh, with the textures that vary it over the surface. The textures are
+/// mapped by the texture coordinates of the mesh and ignored if it has none.
+pub struct MeshMaterial {
+    pub material: Material,
+    /// Texture whose colors are multiplied with the color of the material
+    pub diffuse_map: Option<Texture>,
+    /// Height map that perturbs the normals, and the height of its brightest color
+    pub bump_map: Option<(Texture, f64)>,
+}
+
+/// Materials of the individual triangles of a mesh
+pub struct FaceMaterials {
+    pub materials: Vec<MeshMaterial>,
+    /// Index into `materials` for each triangle, or `None` for the material of the whole mesh
+    pub indices: Vec<Option<usize>>,
+}
+
+/// Triangles as read from a mesh file, with the normals, colors and texture coordinates at their
+/// vertices and the materials of the faces if the file has them. Vertices that several triangles
+/// share are stored once.
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub colors: Option<Vec<Vec3>>,
+    pub texcoords: Option<Vec<(f64, f64)>>,
+    /// Indices of the vertices of each triangle
+    pub triangles: Vec<[u32; 3]>,
+    pub materials: Option<FaceMaterials>,
+}
+
+impl MeshData {
+    /// Creates a mesh from triangles given by the positions of their corners, like those of STL
+    /// files. Corners at exactly the same position become one vertex.
+    pub fn from_triangle_soup(soup: &[[Vec3; 3]]) -> std::io::Result<MeshData> {
+        let mut positions = Vec::new();
+        let mut vertices = HashMap::<[u64; 3], u32>::new();
+        let mut triangles = Vec::with_capacity(soup.len());
+        for corners in soup {
+            let mut triangle = [0; 3];
+            for (vertex, p) in triangle.iter_mut().zip(corners) {
+                let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+                *vertex = match vertices.get(&key) {
+                    Some(&i) => i,
+                    None => {
+                        let i = vertex_index(positions.len())?;
+                        positions.push(*p);
+                        vertices.insert(key, i);
+                        i
+                    }
+                };
+            }
+            triangles.push(triangle);
         }
+        Ok(MeshData {
+            positions,
+            normals: None,
+            colors: None,
+            texcoords: None,
+            triangles,
+            materials: None,
+        })
     }
+}
+
+/// Vertex positions in double or single precision
+enum Positions {
+    Double(Vec<Vec3>),
+    /// Half the memory, for large meshes like 3D scans
+    Single(Vec<[f32; 3]>),
+}
 
-    // Obj face indices are one-based, so shift to zero-based array indices
-    Ok(faces
-        .into_iter()
-        .map(|(i1, i2, i3)| Triangle::new(vertices[i1 - 1], vertices[i2 - 1], vertices[i3 - 1]))
-        .collect())
+fn to_f32(v: Vec3) -> [f32; 3] {
+    [v.x as f32, v.y as f32, v.z as f32]
+}
+
+fn from_f32(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0] as f64, v[1] as f64, v[2] as f64)
 }
 
 pub struct TriangleMesh {
-    triangles: Vec<Triangle<Vec3>>,
+    positions: Positions,
+    /// Indices of the vertices of each triangle
+    triangles: Vec<[u32; 3]>,
+    /// Vertex normals, interpolated over the triangles for smooth shading
+    normals: Option<Vec<Vec3>>,
+    /// Vertex colors, interpolated over the triangles. They replace the color of the material.
+    colors: Option<Vec<Vec3>>,
+    /// Texture coordinates, interpolated over the triangles. They are the uv coordinates of hits.
+    texcoords: Option<Vec<(f64, f64)>>,
+    /// Materials of individual triangles, which replace the material of the mesh
+    face_materials: Option<FaceMaterials>,
     material: Material,
+    /// Corners of the bounding box of the triangles, rays that miss it miss all of them
+    bounds: (Vec3, Vec3),
+    /// The bounding volume hierarchy over the clusters of `CLUSTER_SIZE` consecutive
+    /// triangles. The root is the last node, there are no nodes if there are no triangles.
+    nodes: Vec<BvhNode>,
+    kernels: Kernels,
+}
+
+/// Number of consecutive triangles that share a bounding box. The triangles of a cluster that
+/// is hit are tested in batches of four.
+const CLUSTER_SIZE: usize = 8;
+
+/// A node of a bounding volume hierarchy with four children, whose boxes are tested at once.
+#[derive(Debug, Clone)]
+struct BvhNode {
+    /// The bounding boxes of the children. Missing children have empty boxes.
+    boxes: Boxes4,
+    /// Index of the first of the consecutive children, which are nodes or clusters
+    first_child: usize,
+    /// Whether the children are clusters
+    leaf: bool,
 }
 
-fn map_vertices<T, U, F>(src: &[Triangle<T>], trans: F) -> Vec<Triangle<U>>
-where
-    F: Fn(&T) -> U,
-{
-    src.iter()
-        .map(|Triangle { v1, v2, v3 }| Triangle::new(trans(v1), trans(v2), trans(v3)))
-        .collect()
+/// A node or cluster to visit during the traversal of the hierarchy
+#[derive(Debug, Clone, Copy)]
+enum BvhChild {
+    Node(usize),
+    Cluster(usize),
+}
+
+/// Maximum number of children waiting to be visited during a traversal. A node replaces itself
+/// by at most four children, so a hierarchy with 16 levels, for more than 2^32 triangles, leaves
+/// at most 3 * 16 + 4 of them.
+const BVH_STACK_SIZE: usize = 64;
+
+/// Builds the hierarchy over the boxes of the clusters bottom-up, by grouping four consecutive
+/// boxes of each level under a node. As the triangles are sorted along a Z-order curve,
+/// consecutive boxes are close to each other. Returns the nodes with the root last.
+fn build_bvh(cluster_boxes: Vec<(Vec3, Vec3)>) -> Vec<BvhNode> {
+    let mut nodes = Vec::new();
+    let mut level = cluster_boxes;
+    // Index of the first box of the level, among the clusters or the nodes
+    let mut first = 0;
+    let mut leaf = true;
+    while !level.is_empty() {
+        let level_start = nodes.len();
+        for (i, children) in level.chunks(4).enumerate() {
+            nodes.push(BvhNode {
+                boxes: Boxes4::new(children),
+                first_child: first + i * 4,
+                leaf,
+            });
+        }
+        if nodes.len() - level_start == 1 {
+            break;
+        }
+        level = level
+            .chunks(4)
+            .map(|children| bounds(children.iter().flat_map(|&(min, max)| [min, max])))
+            .collect();
+        first = level_start;
+        leaf = false;
+    }
+    nodes
+}
+
+/// Returns the minimum and maximum corner of the box around the `positions`.
+fn bounds(positions: impl Iterator<Item = Vec3>) -> (Vec3, Vec3) {
+    let infinity = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    positions.fold((infinity, -infinity), |(min, max), p| {
+        (
+            Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+            Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+        )
+    })
+}
+
+/// Spreads the lowest 10 bits of `v` to every third bit.
+fn spread_bits(v: u32) -> u32 {
+    let v = (v | (v << 16)) & 0x030000FF;
+    let v = (v | (v << 8)) & 0x0300F00F;
+    let v = (v | (v << 4)) & 0x030C30C3;
+    (v | (v << 2)) & 0x09249249
+}
+
+/// Sorts the triangles along a Z-order curve through their centroids, so that consecutive
+/// triangles are close to each other and the boxes around clusters of them are small. The
+/// materials of the faces are sorted along.
+fn sort_spatially(
+    positions: &[Vec3],
+    triangles: &mut Vec<[u32; 3]>,
+    materials: &mut Option<FaceMaterials>,
+) {
+    let centroid = |triangle: &[u32; 3]| {
+        triangle.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &i| {
+            sum + positions[i as usize]
+        }) * (1.0 / 3.0)
+    };
+    let (min, max) = bounds(triangles.iter().map(centroid));
+    // Position within the box around the centroids in 1024 steps per axis
+    let quantize = |v: f64, min: f64, max: f64| {
+        if max > min {
+            ((v - min) / (max - min) * 1023.0) as u32
+        } else {
+            0
+        }
+    };
+    let key = |triangle: &[u32; 3]| {
+        let c = centroid(triangle);
+        spread_bits(quantize(c.x, min.x, max.x))
+            | spread_bits(quantize(c.y, min.y, max.y)) << 1
+            | spread_bits(quantize(c.z, min.z, max.z)) << 2
+    };
+
+    let mut order: Vec<usize> = (0..triangles.len()).collect();
+    order.sort_by_cached_key(|&i| key(&triangles[i]));
+    *triangles = order.iter().map(|&i| triangles[i]).collect();
+    if let Some(materials) = materials {
+        materials.indices = order.iter().map(|&i| materials.indices[i]).collect();
+    }
 }
 
 impl TriangleMesh {
+    /// Creates a mesh whose triangles have `material`, unless `data` assigns them another one.
+    pub fn new(data: MeshData, material: Material) -> TriangleMesh {
+        let MeshData {
+            positions,
+            mut triangles,
+            mut materials,
+            ..
+        } = data;
+        sort_spatially(&positions, &mut triangles, &mut materials);
+        let mut mesh = TriangleMesh {
+            positions: Positions::Double(positions),
+            triangles,
+            normals: data.normals,
+            colors: data.colors,
+            texcoords: data.texcoords,
+            face_materials: materials,
+            material,
+            bounds: bounds(std::iter::empty()),
+            nodes: Vec::new(),
+            kernels: Kernels::detect(),
+        };
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// Intersects the mesh with `kernels` instead of the fastest ones that the CPU supports,
+    /// e.g. to compare their results.
+    pub fn with_kernels(self, kernels: Kernels) -> TriangleMesh {
+        TriangleMesh { kernels, ..self }
+    }
+
+    /// Computes the bounding box of the mesh and the hierarchy over its clusters from the
+    /// positions.
+    fn update_bounds(&mut self) {
+        let count = self.triangles.len();
+        self.bounds = bounds((0..count).flat_map(|i| self.vertices(i)));
+        let boxes: Vec<(Vec3, Vec3)> = (0..count)
+            .step_by(CLUSTER_SIZE)
+            .map(|first| {
+                let end = (first + CLUSTER_SIZE).min(count);
+                bounds((first..end).flat_map(|i| self.vertices(i)))
+            })
+            .collect();
+        self.nodes = build_bvh(boxes);
+    }
+
+    /// Stores the positions of the vertices in single precision, which is enough for most meshes
+    /// and lets large ones fit into memory.
+    pub fn with_single_precision(self) -> TriangleMesh {
+        let positions = match self.positions {
+            Positions::Double(positions) => {
+                Positions::Single(positions.into_iter().map(to_f32).collect())
+            }
+            single => single,
+        };
+        let mut mesh = TriangleMesh { positions, ..self };
+        // Rounding to single precision may move vertices out of the boxes
+        mesh.update_bounds();
+        mesh
+    }
+
+    /// Loads a mesh from an OBJ, PLY or STL file, selected by the extension of `path`. The
+    /// coordinates are used as they are in the file.
+    pub fn from_file(path: &Path, material: Material) -> std::io::Result<TriangleMesh> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        let data = stats::time(Phase::MeshParse, || match extension.as_deref() {
+            Some("obj") => parse_obj(path),
+            Some("ply") => ply::parse_ply(path),
+            Some("stl") => stl::parse_stl(path),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown mesh file type '{}'", path.display()),
+            )),
+        })?;
+        Ok(TriangleMesh::new(data, material))
+    }
+
+    /// Loads the Stanford bunny from an OBJ file, scaled and placed for the demo scene.
     pub fn from_obj_file(file_name: &str, material: Material) -> std::io::Result<TriangleMesh> {
-        let triangles = parse_obj(file_name)?;
-
-        let triangles = map_vertices(&triangles, |v| {
-            Vec3::new(
-                v.x * 10000.0,
-                v.y * -10000.0 + 500.0,
-                -v.z * 10000.0 + 1500.0,
-            )
-        });
+        let data = stats::time(Phase::MeshParse, || parse_obj(Path::new(file_name)))?;
 
-        Ok(TriangleMesh {
-            triangles,
+        let positions = data
+            .positions
+            .iter()
+            .map(|v| {
+                Vec3::new(
+                    v.x * 10000.0,
+                    v.y * -10000.0 + 500.0,
+                    -v.z * 10000.0 + 1500.0,
+                )
+            })
+            .collect();
+        let normals = data
+            .normals
+            .as_ref()
+            .map(|normals| normals.iter().map(|n| Vec3::new(n.x, -n.y, -n.z)).collect());
+
+        Ok(TriangleMesh::new(
+            MeshData {
+                positions,
+                normals,
+                ..data
+            },
             material,
+        ))
+    }
+
+    /// Returns the positions of the vertices of the triangle with `index`.
+    fn vertices(&self, index: usize) -> [Vec3; 3] {
+        let vertices = self.triangles[index].map(|i| i as usize);
+        match &self.positions {
+            Positions::Double(positions) => vertices.map(|i| positions[i]),
+            Positions::Single(positions) => vertices.map(|i| from_f32(positions[i])),
+        }
+    }
+
+    /// Intersects the lines through the ray with the up to four triangles from index `first`
+    /// on at once, like `intersect_line()`.
+    fn intersect_lines4(&self, first: usize, ray: &ShearedRay) -> [Option<TriangleHit>; 4] {
+        let count = (self.triangles.len() - first).min(4);
+        stats::count(Counter::TriangleTests, count as u64);
+        // Missing triangles are degenerate, no ray hits them
+        let triangles = std::array::from_fn(|lane| {
+            if lane < count {
+                self.vertices(first + lane)
+            } else {
+                [Vec3::new(0.0, 0.0, 0.0); 3]
+            }
+        });
+        let hits = self
+            .kernels
+            .intersect_triangles_sheared(ray, &Triangles4::new(&triangles));
+        std::array::from_fn(|lane| hits[lane].filter(|_| lane < count))
+    }
+
+    /// Intersects the up to four triangles from index `first` on at once. Returns the index,
+    /// the distance and the barycentric coordinates of the hits between `t_min` and `t_max`.
+    /// Hits closer than the rounding error of their distance are ignored, as they might be
+    /// behind the origin.
+    fn intersect4(
+        &self,
+        first: usize,
+        ray: &ShearedRay,
+        t_min: f64,
+        t_max: f64,
+    ) -> [Option<(usize, f64, f64, f64)>; 4] {
+        let hits = self.intersect_lines4(first, ray);
+        std::array::from_fn(|lane| {
+            let (distance, u, v, error) = hits[lane]?;
+            (distance > t_min.max(error) && distance < t_max).then_some((
+                first + lane,
+                distance,
+                u,
+                v,
+            ))
         })
     }
+
+    /// Calls `visit` for the clusters whose boxes the ray passes through within its interval,
+    /// from front to back by the distance at which the ray enters them. `visit` may shorten the
+    /// interval, after which the clusters that the ray enters beyond it are skipped, and stops
+    /// the traversal by returning true.
+    fn traverse(&self, ray: &mut Ray, mut visit: impl FnMut(usize, &mut Ray) -> bool) {
+        let Some(root) = self.nodes.len().checked_sub(1) else {
+            return;
+        };
+        let mut stack = [(BvhChild::Node(root), 0.0); BVH_STACK_SIZE];
+        stack[0].1 = ray.t_min;
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let (child, near) = stack[len];
+            if near > ray.t_max {
+                continue;
+            }
+            match child {
+                BvhChild::Cluster(cluster) => {
+                    if visit(cluster, ray) {
+                        return;
+                    }
+                }
+                BvhChild::Node(index) => {
+                    stats::count(Counter::BvhNodes, 1);
+                    let node = &self.nodes[index];
+                    let hits = self.kernels.hit_boxes(ray, &node.boxes);
+                    let mut children = [(child, 0.0); 4];
+                    let mut count = 0;
+                    for (lane, near) in hits.into_iter().enumerate() {
+                        let Some(near) = near else { continue };
+                        let index = node.first_child + lane;
+                        let child = if node.leaf {
+                            BvhChild::Cluster(index)
+                        } else {
+                            BvhChild::Node(index)
+                        };
+                        children[count] = (child, near);
+                        count += 1;
+                    }
+                    // The nearest child is pushed last, to be visited first
+                    children[..count].sort_by(|(_, a), (_, b)| b.total_cmp(a));
+                    stack[len..len + count].copy_from_slice(&children[..count]);
+                    len += count;
+                }
+            }
+        }
+    }
+
+    /// Returns the first triangles of the batches of four in the cluster with `index`.
+    fn batches(&self, index: usize) -> impl Iterator<Item = usize> {
+        let end = ((index + 1) * CLUSTER_SIZE).min(self.triangles.len());
+        (index * CLUSTER_SIZE..end).step_by(4)
+    }
+
+    /// Returns the part of the ray's interval within the bounding box of the mesh. Rays that
+    /// miss the box miss all triangles.
+    fn clip(&self, ray: &Ray) -> Option<(f64, f64)> {
+        ray.box_interval(&self.bounds.0, &self.bounds.1)
+    }
+}
+
+impl TriangleMesh {
+    /// Describes the hit of the triangle with `index` at the barycentric coordinates (`u`, `v`).
+    fn hit_record(&self, index: usize, distance: f64, u: f64, v: f64) -> HitRecord<'_> {
+        let vertices = &self.triangles[index];
+        let mut normal = match &self.normals {
+            Some(normals) => interpolate(normals, vertices, u, v).normalized(),
+            None => {
+                let [v1, v2, v3] = self.vertices(index);
+                -(v3 - v1).cross(v2 - v1).normalized()
+            }
+        };
+        let mut color = self
+            .colors
+            .as_ref()
+            .map(|colors| interpolate(colors, vertices, u, v));
+        let texcoords = self
+            .texcoords
+            .as_ref()
+            .map(|texcoords| vertices.map(|i| texcoords[i as usize]));
+        let uv = match texcoords {
+            Some([(u1, v1), (u2, v2), (u3, v3)]) => (
+                u1 * (1.0 - u - v) + u2 * u + u3 * v,
+                v1 * (1.0 - u - v) + v2 * u + v3 * v,
+            ),
+            None => (u, v),
+        };
+
+        let face_material = self.face_materials.as_ref().and_then(|face_materials| {
+            Some(&face_materials.materials[face_materials.indices[index]?])
+        });
+        if let (Some(face_material), Some(texcoords)) = (face_material, texcoords) {
+            if let Some(diffuse_map) = &face_material.diffuse_map {
+                let base = color.unwrap_or(face_material.material.color);
+                color = Some(diffuse_map.sample(uv).mul_elementwise(&base));
+            }
+            if let Some((bump_map, scale)) = &face_material.bump_map {
+                let gradient = bump_map.gradient(uv);
+                normal = self.bump(index, texcoords, normal, gradient, *scale);
+            }
+        }
+
+        HitRecord {
+            distance,
+            object: self,
+            normal,
+            uv,
+            color,
+            material: face_material.map(|face_material| &face_material.material),
+        }
+    }
+
+    /// Tilts `normal` as if the triangle with `index` was displaced along it by a height map with
+    /// the `gradient` over the `texcoords` of its vertices, scaled by `scale`.
+    fn bump(
+        &self,
+        index: usize,
+        [(u1, v1), (u2, v2), (u3, v3)]: [(f64, f64); 3],
+        normal: Vec3,
+        (dh_du, dh_dv): (f64, f64),
+        scale: f64,
+    ) -> Vec3 {
+        let [p1, p2, p3] = self.vertices(index);
+        let (e1, e2) = (p2 - p1, p3 - p1);
+        let t1 = (u2 - u1, v2 - v1);
+        let t2 = (u3 - u1, v3 - v1);
+        let det = t1.0 * t2.1 - t2.0 * t1.1;
+        if det.abs() < f64::EPSILON {
+            return normal;
+        }
+        // Changes of the position per unit of u and of v
+        let dp_du = (e1 * t2.1 - e2 * t1.1) * (1.0 / det);
+        let dp_dv = (e2 * t1.0 - e1 * t2.0) * (1.0 / det);
+
+        // The gradient of the height along the surface is the combination of dp_du and dp_dv
+        // whose projections onto them are the changes of the height per unit of u and v
+        let (a, b, c) = (dp_du.dot(dp_du), dp_du.dot(dp_dv), dp_dv.dot(dp_dv));
+        let det = a * c - b * b;
+        if det.abs() < f64::EPSILON {
+            return normal;
+        }
+        let alpha = (c * dh_du - b * dh_dv) / det;
+        let beta = (a * dh_dv - b * dh_du) / det;
+        let gradient = dp_du * alpha + dp_dv * beta;
+        (normal - gradient * scale).normalized()
+    }
 }
 
 impl Object3D for TriangleMesh {
-    fn hit(&self, ray_src: &Vec3, ray_dir: &Vec3) -> Option<HitRecord> {
-        let mut best_hit: Option<HitRecord> = None;
-        for triangle in &self.triangles {
-            if let Some(hit_pos) = triangle.intersects(*ray_src, *ray_dir) {
-                let distance = (ray_src - &hit_pos).len();
-                let this_is_best = match &best_hit {
-                    Some(hit) => hit.distance > distance,
-                    None => true,
-                };
-                if this_is_best {
-                    let e1 = triangle.v3 - triangle.v1;
-                    let e2 = triangle.v2 - triangle.v1;
-                    best_hit = Some(
-                        HitRecord { distance, object: self, normal: /*-ray_dir*/-e1.cross(e2).normalized() },
-                    );
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        self.clip(ray)?;
+        let sheared = ShearedRay::new(ray);
+        // Only hits closer than the best one so far are found, and only the clusters that may
+        // contain them are tested
+        let mut ray = *ray;
+        let mut best_hit = None;
+        self.traverse(&mut ray, |cluster, ray| {
+            for first in self.batches(cluster) {
+                let hits = self.intersect4(first, &sheared, ray.t_min, ray.t_max);
+                for (index, distance, u, v) in hits.into_iter().flatten() {
+                    if distance < ray.t_max {
+                        ray.t_max = distance;
+                        best_hit = Some((index, distance, u, v));
+                    }
                 }
             }
-        }
+            false
+        });
 
-        best_hit
+        best_hit.map(|(index, distance, u, v)| self.hit_record(index, distance, u, v))
     }
 
     fn get_material(&self) -> &Material {
         &self.material
     }
+
+    fn materials(&self) -> Vec<&Material> {
+        let mut materials = vec![&self.material];
+        if let Some(face_materials) = &self.face_materials {
+            materials.extend(face_materials.materials.iter().map(|m| &m.material));
+        }
+        materials
+    }
+
+    fn hit_any(&self, ray: &Ray) -> bool {
+        if self.clip(ray).is_none() {
+            return false;
+        }
+        let sheared = ShearedRay::new(ray);
+        // Any hit will do, so the clusters are only visited until the first one
+        let mut ray = *ray;
+        let mut hit = false;
+        self.traverse(&mut ray, |cluster, ray| {
+            hit = self.batches(cluster).any(|first| {
+                let hits = self.intersect4(first, &sheared, ray.t_min, ray.t_max);
+                hits.iter().any(Option::is_some)
+            });
+            hit
+        });
+        hit
+    }
+
+    /// Assumes that the mesh is closed, so that the ray alternately enters and leaves it. Where
+    /// the line crosses the surface an odd number of times, e.g. through a hole in the mesh, it
+    /// is unclear which parts are inside, and no intervals are returned.
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let line = ray.with_interval(f64::NEG_INFINITY, f64::INFINITY);
+        let Some((near, _)) = self.clip(&line) else {
+            return Vec::new();
+        };
+        // The box tests are only conservative in front of the ray origin, so the hierarchy is
+        // traversed from a point before the mesh. The triangles are intersected with the line
+        // through the original ray, to get the same distances as `hit()`.
+        let margin = (self.bounds.1 - self.bounds.0).len();
+        let mut traversal = Ray::new(ray.at(near - margin), *ray.direction(), ray.time);
+        let sheared = ShearedRay::new(ray);
+        let mut hits = Vec::new();
+        self.traverse(&mut traversal, |cluster, _| {
+            for first in self.batches(cluster) {
+                let lines = self.intersect_lines4(first, &sheared);
+                hits.extend(
+                    (first..)
+                        .zip(lines)
+                        .filter_map(|(index, hit)| Some((index, hit?))),
+                );
+            }
+            false
+        });
+        hits.sort_by(|(_, (a, ..)), (_, (b, ..))| a.total_cmp(b));
+        // A ray through an edge or a vertex hits all triangles that share it, at the same
+        // distance up to rounding errors. They count as one hit.
+        hits.dedup_by(
+            |(_, (distance, _, _, error)), (_, (previous, _, _, previous_error))| {
+                *distance - *previous <= *error + *previous_error
+            },
+        );
+
+        if hits.len() % 2 != 0 {
+            return Vec::new();
+        }
+        hits.chunks(2)
+            .map(|pair| {
+                let [enter, exit] = [pair[0], pair[1]]
+                    .map(|(index, (distance, u, v, _))| self.hit_record(index, distance, u, v));
+                Interval { enter, exit }
+            })
+            .collect()
+    }
 }
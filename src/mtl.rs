@@ -0,0 +1,150 @@
+//! Reads the material libraries (MTL files) that OBJ files refer to.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::math3d::Vec3;
+use crate::mesh::MeshMaterial;
+use crate::scene_objects::Material;
+use crate::texture::Texture;
+
+/// Reads the materials of an MTL file by their names, mapped onto `Material`:
+///
+/// - `Kd` is the color, and `map_Kd` a texture that is multiplied with it.
+/// - `Ks` is the strength of the highlights and `Ns` their exponent. Illumination models
+///   (`illum`) below 2 have no highlights.
+/// - Models 3, 4 and 6 reflect the scene by `Ks`, models 5 and 7 by the Fresnel reflectance at
+///   normal incidence for the index of refraction `Ni`. The reflectance is the mean over the
+///   colors, as reflections are not tinted.
+/// - `d`, or 1 - `Tr`, is the opacity. Light passes through straight, without refraction.
+/// - `map_Bump` or `bump` is a height map, whose `-bm` option scales the heights.
+///
+/// Textures are read from PNG files relative to the MTL file.
+pub fn parse_mtl(path: &Path) -> std::io::Result<HashMap<String, MeshMaterial>> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_ascii_whitespace();
+        let keyword = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.collect();
+        let invalid = || invalid_data(&format!("Invalid MTL line {}: '{line}'", line_number + 1));
+        let number = |i: usize| -> std::io::Result<f64> {
+            args.get(i)
+                .and_then(|arg| arg.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let color = || -> std::io::Result<Vec3> {
+            let r = number(0)?;
+            // A single value is a gray
+            match args.len() {
+                1 => Ok(Vec3::new(r, r, r)),
+                _ => Ok(Vec3::new(r, number(1)?, number(2)?)),
+            }
+        };
+
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material.into_mesh_material());
+            }
+            current = Some((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        let Some((_, material)) = current.as_mut() else {
+            return Err(invalid());
+        };
+        match keyword {
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ns" => material.shininess = number(0)?,
+            "Ni" => material.index_of_refraction = number(0)?,
+            "d" => material.opacity = number(0)?,
+            "Tr" => material.opacity = 1.0 - number(0)?,
+            "illum" => material.illumination = number(0)? as u32,
+            "map_Kd" => {
+                // The file name is the last argument, after any options
+                let file = args.last().ok_or_else(invalid)?;
+                material.diffuse_map = Some(Texture::load(&dir.join(file))?);
+            }
+            "map_Bump" | "map_bump" | "bump" => {
+                let file = args.last().ok_or_else(invalid)?;
+                let scale = match args.iter().position(|&arg| arg == "-bm") {
+                    Some(i) => number(i + 1)?,
+                    None => 1.0,
+                };
+                material.bump_map = Some((Texture::load(&dir.join(file))?, scale));
+            }
+            // Ambient and emissive colors and other maps have no equivalent
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material.into_mesh_material());
+    }
+
+    Ok(materials)
+}
+
+/// The parameters of an MTL material, with their defaults
+struct MtlMaterial {
+    diffuse: Vec3,
+    specular: Vec3,
+    shininess: f64,
+    index_of_refraction: f64,
+    opacity: f64,
+    illumination: u32,
+    diffuse_map: Option<Texture>,
+    bump_map: Option<(Texture, f64)>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Vec3::new(0.8, 0.8, 0.8),
+            specular: Vec3::new(0.0, 0.0, 0.0),
+            shininess: 10.0,
+            index_of_refraction: 1.0,
+            opacity: 1.0,
+            illumination: 2,
+            diffuse_map: None,
+            bump_map: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    fn into_mesh_material(self) -> MeshMaterial {
+        let mean = |v: Vec3| (v.x + v.y + v.z) / 3.0;
+        let specular_strength = if self.illumination >= 2 {
+            mean(self.specular)
+        } else {
+            0.0
+        };
+        let reflectance = match self.illumination {
+            3 | 4 | 6 => mean(self.specular),
+            5 | 7 => {
+                let n = self.index_of_refraction;
+                ((n - 1.0) / (n + 1.0)).powi(2)
+            }
+            _ => 0.0,
+        };
+        let mut material =
+            Material::new(self.diffuse, reflectance, specular_strength, self.shininess);
+        material.opacity = self.opacity.clamp(0.0, 1.0);
+        MeshMaterial {
+            material,
+            diffuse_map: self.diffuse_map,
+            bump_map: self.bump_map,
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
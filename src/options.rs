@@ -0,0 +1,385 @@
+use std::time::Duration;
+
+use rust_raytracer::aov::Aov;
+use rust_raytracer::camera::{ApertureShape, Projection};
+use rust_raytracer::tiles::TileOrder;
+
+const USAGE: &str = "Usage: rust-raytracer [OPTIONS]
+
+Options:
+      --projection <PROJECTION>
+                            Camera projection: perspective or stereo360 [default: stereo360]
+      --aperture <RADIUS>   Render with depth of field, using a lens with the given aperture
+                            radius
+      --focal-distance <DISTANCE>
+                            Distance at which objects are in focus [default: 400]
+      --aperture-blades <N> Shape the aperture as a polygon with N blades instead of a circle
+  -o, --output <FILE>       Write the rendered image to FILE. For animations, the run of '#'
+                            in FILE is replaced by the frame number. [default: image.png, or
+                            frame_####.png for animations]
+      --frames <FRAMES>     Render frames of the animation instead of a still image: a single
+                            frame N, a range A-B or all
+      --fps <RATE>          Frames per second of the animation [default: 24]
+      --shutter <FRACTION>  Fraction of the frame duration during which the shutter is open,
+                            to blur motion [default: 0]
+      --passes <N>          Number of samples per pixel, rendered in N progressive passes
+                            [default: 1]
+      --snapshot-interval <SECONDS>
+                            Write the intermediate image every SECONDS seconds
+      --snapshot-passes <N> Write the intermediate image every N completed passes
+      --seed <N>            Seed from which all random numbers of the render are derived. Renders
+                            with the same seed are identical. [default: 0]
+      --threads <N>         Number of worker threads [default: number of CPU cores]
+      --checkpoint <FILE>   Periodically save the render state to FILE, as well as when
+                            interrupted with Ctrl-C
+      --checkpoint-interval <SECONDS>
+                            Time between two checkpoints [default: 600]
+      --resume <FILE>       Continue the render saved in the checkpoint FILE. The number of
+                            passes may be increased when resuming.
+  -q, --quiet               Do not show a progress bar
+      --tile-size <PIXELS>  Edge length of the square render tiles [default: 32]
+      --tile-order <ORDER>  Order in which tiles are rendered: scanline, spiral or hilbert
+                            [default: hilbert]
+      --ao <SAMPLES>        Darken the ambient light by ambient occlusion, using SAMPLES rays
+                            per shading point
+      --ao-distance <DISTANCE>
+                            Geometry farther away does not occlude [default: 200]
+      --ao-all-depths       Compute ambient occlusion at reflected hits as well, not only at
+                            the hits of camera rays
+      --ao-only             Render only the ambient occlusion of the visible surfaces
+      --aov <AOVS>          Also render the comma-separated AOVs and write each to a PFM file
+                            next to the image: depth, normal, albedo, object-id, material-id,
+                            position and ao
+      --denoise             Filter the noise out of the image before writing it. This also
+                            renders the depth, normal and albedo AOVs that guide the filter.
+      --denoise-iterations <N>
+                            Number of filter iterations from 1 to 10, more remove coarser
+                            noise [default: 5]
+      --environment <FILE>  Surround the scene by an equirectangular HDR environment map, read
+                            from a .hdr or .pfm file
+      --environment-rotation <DEGREES>
+                            Rotate the environment map around the vertical axis [default: 0]
+      --environment-intensity <FACTOR>
+                            Scale the brightness of the environment map or the sky [default: 1]
+      --sky                 Surround the scene by a daylight sky, with the sun in the direction
+                            of the light
+      --turbidity <T>       Haze of the sky, from 2 (clear) to 10 (hazy) [default: 3]
+      --ibl <SAMPLES>       Light the objects by the environment instead of a constant ambient
+                            light, using SAMPLES rays per shading point
+      --fog <DENSITY>       Fill the scene with fog that absorbs or scatters the fraction
+                            DENSITY of the light per unit of distance, e.g. 0.0005
+      --stats               Print render statistics and timings when done
+      --stats-json <FILE>   Write render statistics and timings as JSON to FILE
+  -h, --help                Print this help";
+
+/// The frames of the animation to render.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameRange {
+    /// From the first to the last frame of the animation
+    All,
+    /// First and last frame, inclusive. Frame 1 is at time zero.
+    Range(u32, u32),
+}
+
+impl std::str::FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(FrameRange::All);
+        }
+        let invalid = || format!("Invalid frame range '{s}'");
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let first: u32 = first.parse().map_err(|_| invalid())?;
+        let last: u32 = last.parse().map_err(|_| invalid())?;
+        if first == 0 || last < first {
+            return Err(invalid());
+        }
+        Ok(FrameRange::Range(first, last))
+    }
+}
+
+/// Command line options of the renderer.
+pub struct Options {
+    pub projection: Projection,
+    pub aperture_radius: Option<f64>,
+    pub focal_distance: f64,
+    pub aperture_shape: ApertureShape,
+    pub output: String,
+    /// Frames of the animation to render, `None` for a still image at time zero
+    pub frames: Option<FrameRange>,
+    pub fps: f64,
+    /// Shutter time as a fraction of the frame duration
+    pub shutter: f64,
+    pub passes: usize,
+    pub snapshot_interval: Option<Duration>,
+    pub snapshot_passes: Option<usize>,
+    pub seed: u64,
+    pub threads: Option<usize>,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: Option<Duration>,
+    pub resume: Option<String>,
+    pub quiet: bool,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub ao_samples: Option<u32>,
+    pub ao_distance: f64,
+    pub ao_all_depths: bool,
+    pub ao_only: bool,
+    pub aovs: Vec<Aov>,
+    /// Number of denoising iterations, `None` to not denoise
+    pub denoise_iterations: Option<u32>,
+    pub environment: Option<String>,
+    /// In degrees
+    pub environment_rotation: f64,
+    pub environment_intensity: f64,
+    pub sky: bool,
+    pub turbidity: f64,
+    pub ibl_samples: Option<u32>,
+    /// Density of the fog, `None` for no fog
+    pub fog: Option<f64>,
+    pub stats: bool,
+    pub stats_json: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            projection: Projection::Stereo360,
+            aperture_radius: None,
+            focal_distance: 400.0,
+            aperture_shape: ApertureShape::Circle,
+            output: "image.png".to_string(),
+            frames: None,
+            fps: 24.0,
+            shutter: 0.0,
+            passes: 1,
+            snapshot_interval: None,
+            snapshot_passes: None,
+            seed: 0,
+            threads: None,
+            checkpoint: None,
+            checkpoint_interval: Some(Duration::from_secs(600)),
+            resume: None,
+            quiet: false,
+            tile_size: 32,
+            tile_order: TileOrder::Hilbert,
+            ao_samples: None,
+            ao_distance: 200.0,
+            ao_all_depths: false,
+            ao_only: false,
+            aovs: Vec::new(),
+            denoise_iterations: None,
+            environment: None,
+            environment_rotation: 0.0,
+            environment_intensity: 1.0,
+            sky: false,
+            turbidity: 3.0,
+            ibl_samples: None,
+            fog: None,
+            stats: false,
+            stats_json: None,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the process' command line arguments. Prints the usage and exits on `--help` or
+    /// on invalid arguments.
+    pub fn from_args() -> Options {
+        match Options::parse(std::env::args().skip(1)) {
+            Ok(Some(options)) => options,
+            Ok(None) => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            Err(msg) => {
+                eprintln!("Error: {msg}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// Returns `Ok(None)` if the usage was requested.
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+        let mut options = Options::default();
+        let mut output = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for '{arg}'"))
+            };
+            match arg.as_str() {
+                "--projection" => options.projection = value()?.parse()?,
+                "--aperture" => options.aperture_radius = Some(parse_value(&arg, &value()?)?),
+                "--focal-distance" => options.focal_distance = parse_value(&arg, &value()?)?,
+                "--aperture-blades" => {
+                    let blades = parse_value(&arg, &value()?)?;
+                    if blades < 3 {
+                        return Err("An aperture needs at least three blades".to_string());
+                    }
+                    options.aperture_shape = ApertureShape::Polygon { blades };
+                }
+                "-o" | "--output" => output = Some(value()?),
+                "--frames" => options.frames = Some(value()?.parse()?),
+                "--fps" => options.fps = parse_value(&arg, &value()?)?,
+                "--shutter" => options.shutter = parse_value(&arg, &value()?)?,
+                "--passes" => options.passes = parse_value(&arg, &value()?)?,
+                "--snapshot-interval" => {
+                    options.snapshot_interval = Some(parse_duration(&arg, &value()?)?)
+                }
+                "--snapshot-passes" => {
+                    options.snapshot_passes = Some(parse_value(&arg, &value()?)?)
+                }
+                "--seed" => options.seed = parse_value(&arg, &value()?)?,
+                "--threads" => options.threads = Some(parse_value(&arg, &value()?)?),
+                "--checkpoint" => options.checkpoint = Some(value()?),
+                "--checkpoint-interval" => {
+                    options.checkpoint_interval = Some(parse_duration(&arg, &value()?)?)
+                }
+                "--resume" => options.resume = Some(value()?),
+                "-q" | "--quiet" => options.quiet = true,
+                "--tile-size" => options.tile_size = parse_value(&arg, &value()?)?,
+                "--tile-order" => options.tile_order = value()?.parse()?,
+                "--ao" => options.ao_samples = Some(parse_value(&arg, &value()?)?),
+                "--ao-distance" => options.ao_distance = parse_value(&arg, &value()?)?,
+                "--ao-all-depths" => options.ao_all_depths = true,
+                "--ao-only" => options.ao_only = true,
+                "--aov" => {
+                    for aov in value()?.split(',') {
+                        let aov = aov.parse()?;
+                        if !options.aovs.contains(&aov) {
+                            options.aovs.push(aov);
+                        }
+                    }
+                }
+                "--denoise" => options.denoise_iterations = options.denoise_iterations.or(Some(5)),
+                "--denoise-iterations" => {
+                    options.denoise_iterations = Some(parse_value(&arg, &value()?)?)
+                }
+                "--environment" => options.environment = Some(value()?),
+                "--environment-rotation" => {
+                    options.environment_rotation = parse_value(&arg, &value()?)?
+                }
+                "--environment-intensity" => {
+                    options.environment_intensity = parse_value(&arg, &value()?)?
+                }
+                "--sky" => options.sky = true,
+                "--turbidity" => options.turbidity = parse_value(&arg, &value()?)?,
+                "--ibl" => options.ibl_samples = Some(parse_value(&arg, &value()?)?),
+                "--fog" => options.fog = Some(parse_value(&arg, &value()?)?),
+                "--stats" => options.stats = true,
+                "--stats-json" => options.stats_json = Some(value()?),
+                "-h" | "--help" => return Ok(None),
+                _ => return Err(format!("Unexpected argument '{arg}'")),
+            }
+        }
+
+        if options.frames.is_some() {
+            options.output = output.unwrap_or_else(|| "frame_####.png".to_string());
+            if !options.output.contains('#') {
+                return Err(
+                    "The output of an animation needs a '#' for the frame number".to_string(),
+                );
+            }
+            if options.checkpoint.is_some() || options.resume.is_some() {
+                return Err("Animations cannot be checkpointed".to_string());
+            }
+        } else if let Some(output) = output {
+            options.output = output;
+        }
+
+        if options.fps.is_nan() || options.fps <= 0.0 {
+            return Err("The frame rate must be positive".to_string());
+        }
+
+        if !(0.0..=1.0).contains(&options.shutter) {
+            return Err("The shutter must be open between none and all of a frame".to_string());
+        }
+
+        if options.passes == 0 {
+            return Err("At least one pass needs to be rendered".to_string());
+        }
+
+        if options.snapshot_passes == Some(0) {
+            return Err("Snapshots can only be written every one or more passes".to_string());
+        }
+
+        if options.threads == Some(0) {
+            return Err("At least one worker thread is required".to_string());
+        }
+
+        if options.tile_size == 0 {
+            return Err("The tile size must be at least one pixel".to_string());
+        }
+
+        if options
+            .aperture_radius
+            .is_some_and(|r| r.is_nan() || r < 0.0)
+        {
+            return Err("The aperture radius must not be negative".to_string());
+        }
+
+        if options.focal_distance.is_nan() || options.focal_distance <= 0.0 {
+            return Err("The focal distance must be positive".to_string());
+        }
+
+        if options
+            .denoise_iterations
+            .is_some_and(|n| !(1..=10).contains(&n))
+        {
+            return Err("The number of denoising iterations must be between 1 and 10".to_string());
+        }
+
+        if options.ao_samples == Some(0) {
+            return Err("Ambient occlusion needs at least one sample".to_string());
+        }
+
+        if options.ao_distance.is_nan() || options.ao_distance <= 0.0 {
+            return Err("The ambient occlusion distance must be positive".to_string());
+        }
+
+        if options.environment.is_some() && options.sky {
+            return Err(
+                "The scene is surrounded either by an environment map or by the sky".to_string(),
+            );
+        }
+
+        if !options.environment_rotation.is_finite() {
+            return Err("The environment rotation must be finite".to_string());
+        }
+
+        if options.environment_intensity.is_nan() || options.environment_intensity < 0.0 {
+            return Err("The environment intensity must not be negative".to_string());
+        }
+
+        if !(2.0..=10.0).contains(&options.turbidity) {
+            return Err("The turbidity must be between 2 and 10".to_string());
+        }
+
+        if options.ibl_samples == Some(0) {
+            return Err("Image-based lighting needs at least one sample".to_string());
+        }
+
+        if options
+            .fog
+            .is_some_and(|density| !density.is_finite() || density <= 0.0)
+        {
+            return Err("The fog density must be positive".to_string());
+        }
+
+        Ok(Some(options))
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{value}' for '{arg}'"))
+}
+
+fn parse_duration(arg: &str, value: &str) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(parse_value(arg, value)?)
+        .map_err(|_| format!("Invalid duration '{value}' for '{arg}'"))
+}
@@ -0,0 +1,299 @@
+//! Reads meshes from PLY files, as written by 3D scanners, in the ASCII and both binary formats.
+
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::math3d::Vec3;
+use crate::mesh::{self, MeshData};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// Type of a property value
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Type {
+    fn parse(name: &str) -> std::io::Result<Type> {
+        Ok(match name {
+            "char" | "int8" => Type::I8,
+            "uchar" | "uint8" => Type::U8,
+            "short" | "int16" => Type::I16,
+            "ushort" | "uint16" => Type::U16,
+            "int" | "int32" => Type::I32,
+            "uint" | "uint32" => Type::U32,
+            "float" | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            _ => return Err(invalid_data(&format!("Unknown PLY type '{name}'"))),
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, Type),
+    /// A list of values, preceded by their count
+    List(String, Type, Type),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads the values of the elements, one after the other, as f64. All integer types used in PLY
+/// files fit into it exactly.
+enum Values<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Values<'_> {
+    fn read(&mut self, ty: Type) -> std::io::Result<f64> {
+        let end_of_data = || invalid_data("Unexpected end of PLY data");
+        match self {
+            Values::Ascii(tokens) => {
+                let token = tokens.next().ok_or_else(end_of_data)?;
+                token
+                    .parse()
+                    .map_err(|_| invalid_data(&format!("Invalid PLY value '{token}'")))
+            }
+            Values::Binary { data, big_endian } => {
+                if data.len() < ty.size() {
+                    return Err(end_of_data());
+                }
+                let (bytes, rest) = data.split_at(ty.size());
+                *data = rest;
+                let mut buf = [0u8; 8];
+                buf[..bytes.len()].copy_from_slice(bytes);
+                if *big_endian {
+                    buf[..bytes.len()].reverse();
+                }
+                // The bytes are in little-endian order now
+                Ok(match ty {
+                    Type::I8 => buf[0] as i8 as f64,
+                    Type::U8 => buf[0] as f64,
+                    Type::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    Type::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    Type::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    Type::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    Type::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    Type::F64 => f64::from_le_bytes(buf),
+                })
+            }
+        }
+    }
+}
+
+/// Reads the triangles of a PLY file with their vertex normals and colors, if it has them.
+/// Polygons with more than three vertices are split into triangles. Elements other than vertices
+/// and faces are skipped.
+pub fn parse_ply(path: &Path) -> std::io::Result<MeshData> {
+    let data = std::fs::read(path)?;
+
+    // The header is text, terminated by the end_header line
+    const END_HEADER: &[u8] = b"end_header";
+    let header_end = data
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or_else(|| invalid_data("Missing PLY end_header"))?;
+    let body_start = data[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |i| header_end + i + 1);
+    let header =
+        std::str::from_utf8(&data[..header_end]).map_err(|_| invalid_data("Invalid PLY header"))?;
+    let (format, elements) = parse_header(header)?;
+
+    let mut values = match format {
+        Format::Ascii => Values::Ascii(
+            std::str::from_utf8(&data[body_start..])
+                .map_err(|_| invalid_data("Invalid ASCII PLY data"))?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => Values::Binary {
+            data: &data[body_start..],
+            big_endian: format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut faces: Vec<Vec<usize>> = Vec::new();
+    for element in &elements {
+        for _ in 0..element.count {
+            // Vertex attributes that are not in the file stay zero
+            let mut position = [0.0; 3];
+            let mut normal = [0.0; 3];
+            let mut color = [0.0; 3];
+            let mut face = Vec::new();
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, ty) => {
+                        let value = values.read(*ty)?;
+                        // Integer colors range up to 255, floating point ones up to 1
+                        let color_value = match ty {
+                            Type::F32 | Type::F64 => value,
+                            _ => value / 255.0,
+                        };
+                        match name.as_str() {
+                            "x" => position[0] = value,
+                            "y" => position[1] = value,
+                            "z" => position[2] = value,
+                            "nx" => normal[0] = value,
+                            "ny" => normal[1] = value,
+                            "nz" => normal[2] = value,
+                            "red" | "diffuse_red" => color[0] = color_value,
+                            "green" | "diffuse_green" => color[1] = color_value,
+                            "blue" | "diffuse_blue" => color[2] = color_value,
+                            _ => {}
+                        }
+                    }
+                    Property::List(name, count_type, ty) => {
+                        let count = to_index(values.read(*count_type)?)?;
+                        for _ in 0..count {
+                            let value = values.read(*ty)?;
+                            if name == "vertex_indices" || name == "vertex_index" {
+                                face.push(to_index(value)?);
+                            }
+                        }
+                    }
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => {
+                    positions.push(Vec3::new(position[0], position[1], position[2]));
+                    normals.push(Vec3::new(normal[0], normal[1], normal[2]));
+                    colors.push(Vec3::new(color[0], color[1], color[2]));
+                }
+                "face" => faces.push(face),
+                _ => {}
+            }
+        }
+    }
+
+    let has_property = |names: &[&str]| {
+        elements
+            .iter()
+            .filter(|element| element.name == "vertex")
+            .flat_map(|element| &element.properties)
+            .any(|property| matches!(property, Property::Scalar(name, _) if names.contains(&name.as_str())))
+    };
+    let has_normals = has_property(&["nx"]);
+    let has_colors = has_property(&["red", "diffuse_red"]);
+
+    // Split the polygons into fans of triangles
+    let mut triangles = Vec::new();
+    for face in &faces {
+        if let Some(&i) = face.iter().find(|&&i| i >= positions.len()) {
+            return Err(invalid_data(&format!("PLY vertex index {i} out of range")));
+        }
+        for k in 1..face.len().saturating_sub(1) {
+            triangles.push([
+                mesh::vertex_index(face[0])?,
+                mesh::vertex_index(face[k])?,
+                mesh::vertex_index(face[k + 1])?,
+            ]);
+        }
+    }
+
+    Ok(MeshData {
+        positions,
+        normals: has_normals.then_some(normals),
+        colors: has_colors.then_some(colors),
+        texcoords: None,
+        triangles,
+        materials: None,
+    })
+}
+
+fn parse_header(header: &str) -> std::io::Result<(Format, Vec<Element>)> {
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(invalid_data("Not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let parts: Vec<&str> = line.split_ascii_whitespace().collect();
+        match parts.as_slice() {
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(invalid_data(&format!("Unknown PLY format '{name}'"))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid_data(&format!("Invalid PLY element count '{count}'")))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, ty, name] => {
+                let property =
+                    Property::List(name.to_string(), Type::parse(count_type)?, Type::parse(ty)?);
+                add_property(&mut elements, property)?;
+            }
+            ["property", ty, name] => {
+                let property = Property::Scalar(name.to_string(), Type::parse(ty)?);
+                add_property(&mut elements, property)?;
+            }
+            _ => return Err(invalid_data(&format!("Invalid PLY header line '{line}'"))),
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid_data("Missing PLY format"))?;
+    Ok((format, elements))
+}
+
+fn add_property(elements: &mut [Element], property: Property) -> std::io::Result<()> {
+    elements
+        .last_mut()
+        .ok_or_else(|| invalid_data("PLY property outside of an element"))?
+        .properties
+        .push(property);
+    Ok(())
+}
+
+/// Converts a list count or vertex index, which needs to be a non-negative integer.
+fn to_index(value: f64) -> std::io::Result<usize> {
+    if value >= 0.0 && value.fract() == 0.0 && value < usize::MAX as f64 {
+        Ok(value as usize)
+    } else {
+        Err(invalid_data(&format!("Invalid PLY count or index {value}")))
+    }
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,659 @@
+//! Analytic shapes beyond spheres and planes. Shapes that are not axis-aligned are intersected
+//! in a local coordinate system, in which their axis is the y axis.
+
+use std::f64::consts::PI;
+
+use crate::math3d::{Rotation, Vec3};
+use crate::ray::Ray;
+use crate::scene_objects::{HitRecord, Interval, Material, Object3D};
+
+/// An orthonormal coordinate system whose y axis points along the axis of a shape.
+struct Frame {
+    origin: Vec3,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+}
+
+impl Frame {
+    fn new(origin: Vec3, axis: Vec3) -> Frame {
+        let y = axis.normalized();
+        let (x, z) = y.perpendicular_axes();
+        Frame { origin, x, y, z }
+    }
+
+    fn to_local(&self, p: &Vec3) -> Vec3 {
+        self.to_local_direction(&(p - &self.origin))
+    }
+
+    fn to_local_direction(&self, d: &Vec3) -> Vec3 {
+        Vec3::new(d.dot(self.x), d.dot(self.y), d.dot(self.z))
+    }
+
+    fn to_world_direction(&self, d: &Vec3) -> Vec3 {
+        self.x * d.x + self.y * d.y + self.z * d.z
+    }
+}
+
+/// Distance, normal and uv coordinates of a hit, in the local coordinate system of a shape
+type LocalHit = (f64, Vec3, (f64, f64));
+
+/// Returns the closest of the hits within the interval of the ray.
+fn closest(hits: impl IntoIterator<Item = LocalHit>, ray: &Ray) -> Option<LocalHit> {
+    hits.into_iter()
+        .filter(|hit| ray.contains(hit.0))
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// Converts a hit in the local coordinate system of `object` into a hit of the scene, using
+/// `to_world` for the normal.
+fn hit_record(
+    object: &dyn Object3D,
+    (distance, normal, uv): LocalHit,
+    to_world: impl Fn(Vec3) -> Vec3,
+) -> HitRecord<'_> {
+    HitRecord {
+        distance,
+        object,
+        normal: to_world(normal),
+        uv,
+        color: None,
+        material: None,
+    }
+}
+
+/// Pairs up the hits of a ray with a closed surface, sorted by distance, into the intervals in
+/// which the ray is inside. An odd hit, where the ray grazes the surface, is dropped.
+fn intervals<'a>(
+    object: &'a dyn Object3D,
+    mut hits: Vec<LocalHit>,
+    to_world: impl Fn(Vec3) -> Vec3,
+) -> Vec<Interval<'a>> {
+    hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+    hits.chunks_exact(2)
+        .map(|pair| Interval {
+            enter: hit_record(object, pair[0], &to_world),
+            exit: hit_record(object, pair[1], &to_world),
+        })
+        .collect()
+}
+
+/// Returns the angle of (`x`, `z`) around the y axis, scaled to the range from 0 to 1.
+fn turns(x: f64, z: f64) -> f64 {
+    0.5 + z.atan2(x) / (2.0 * PI)
+}
+
+/// A box whose faces are parallel to the coordinate planes.
+pub struct AxisAlignedBox {
+    min: Vec3,
+    max: Vec3,
+    material: Material,
+}
+
+impl AxisAlignedBox {
+    /// Creates the box spanned by two opposite corners.
+    pub fn new(corner1: Vec3, corner2: Vec3, material: Material) -> AxisAlignedBox {
+        AxisAlignedBox {
+            min: Vec3::new(
+                corner1.x.min(corner2.x),
+                corner1.y.min(corner2.y),
+                corner1.z.min(corner2.z),
+            ),
+            max: Vec3::new(
+                corner1.x.max(corner2.x),
+                corner1.y.max(corner2.y),
+                corner1.z.max(corner2.z),
+            ),
+            material,
+        }
+    }
+}
+
+/// Intersects the line through a ray with the box from `min` to `max` using the slab method.
+/// Returns where the ray enters and leaves the box, with normals pointing out of the box and
+/// uv coordinates spanning each face.
+fn box_hits(min: &Vec3, max: &Vec3, src: &Vec3, dir: &Vec3) -> Vec<LocalHit> {
+    let (min, max) = ([min.x, min.y, min.z], [max.x, max.y, max.z]);
+    let (src, dir) = ([src.x, src.y, src.z], [dir.x, dir.y, dir.z]);
+
+    // Distance and axis at which the ray enters and leaves the box
+    let mut near = (f64::NEG_INFINITY, 0);
+    let mut far = (f64::INFINITY, 0);
+    for axis in 0..3 {
+        if dir[axis] == 0.0 {
+            if src[axis] < min[axis] || src[axis] > max[axis] {
+                return Vec::new();
+            }
+            continue;
+        }
+        let t0 = (min[axis] - src[axis]) / dir[axis];
+        let t1 = (max[axis] - src[axis]) / dir[axis];
+        let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+        if t0 > near.0 {
+            near = (t0, axis);
+        }
+        if t1 < far.0 {
+            far = (t1, axis);
+        }
+    }
+    if near.0 > far.0 {
+        return Vec::new();
+    }
+
+    [near, far]
+        .map(|(t, axis)| {
+            let p = [0, 1, 2].map(|i| src[i] + dir[i] * t);
+            let mut normal = [0.0; 3];
+            normal[axis] = if p[axis] > (min[axis] + max[axis]) * 0.5 {
+                1.0
+            } else {
+                -1.0
+            };
+            let (a1, a2) = ((axis + 1) % 3, (axis + 2) % 3);
+            let uv = (
+                (p[a1] - min[a1]) / (max[a1] - min[a1]),
+                (p[a2] - min[a2]) / (max[a2] - min[a2]),
+            );
+            (t, Vec3::new(normal[0], normal[1], normal[2]), uv)
+        })
+        .to_vec()
+}
+
+impl Object3D for AxisAlignedBox {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        // From inside the box, the ray hits where it leaves
+        let hit = closest(
+            box_hits(&self.min, &self.max, ray.origin(), ray.direction()),
+            ray,
+        )?;
+        Some(hit_record(self, hit, |n| n))
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        intervals(
+            self,
+            box_hits(&self.min, &self.max, ray.origin(), ray.direction()),
+            |n| n,
+        )
+    }
+}
+
+/// A box with an arbitrary orientation.
+pub struct OrientedBox {
+    center: Vec3,
+    half_size: Vec3,
+    rotation: Rotation,
+    inverse_rotation: Rotation,
+    material: Material,
+}
+
+impl OrientedBox {
+    /// Creates a box of `size` around `center` that is rotated by `rotation`.
+    pub fn new(center: Vec3, size: Vec3, rotation: Rotation, material: Material) -> OrientedBox {
+        OrientedBox {
+            center,
+            half_size: size * 0.5,
+            inverse_rotation: rotation.inverse(),
+            rotation,
+            material,
+        }
+    }
+
+    fn local_hits(&self, ray: &Ray) -> Vec<LocalHit> {
+        let src = self.inverse_rotation.rotate(ray.origin() - &self.center);
+        let dir = self.inverse_rotation.rotate(*ray.direction());
+        box_hits(&-self.half_size, &self.half_size, &src, &dir)
+    }
+}
+
+impl Object3D for OrientedBox {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let hit = closest(self.local_hits(ray), ray)?;
+        Some(hit_record(self, hit, |n| self.rotation.rotate(n)))
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let hits = self.local_hits(ray);
+        intervals(self, hits, |n| self.rotation.rotate(n))
+    }
+}
+
+/// Intersects the line through a ray with a capped truncated cone around the y axis, from
+/// radius `r0` at height zero to radius `r1` at `height`. On the side, u goes around the axis
+/// and v along it; on the caps, the uv coordinates span the square around the cap. As the shape
+/// is convex, the ray enters it at the first hit and leaves it at the last one.
+fn frustum_hits(src: &Vec3, dir: &Vec3, r0: f64, r1: f64, height: f64) -> Vec<LocalHit> {
+    // Change of the radius per height
+    let slope = (r1 - r0) / height;
+    let radius_at = |y: f64| r0 + slope * y;
+
+    let mut hits = Vec::new();
+
+    // Points on the side have a distance to the axis of radius_at(y)
+    let r_src = radius_at(src.y);
+    let a = dir.x * dir.x + dir.z * dir.z - slope * slope * dir.y * dir.y;
+    let b = 2.0 * (src.x * dir.x + src.z * dir.z - slope * dir.y * r_src);
+    let c = src.x * src.x + src.z * src.z - r_src * r_src;
+    if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+        for t in [t0, t1] {
+            let p = src + dir * t;
+            // The equation also describes the cone mirrored at its apex
+            if (0.0..=height).contains(&p.y) && radius_at(p.y) >= 0.0 {
+                let normal = Vec3::new(p.x, -slope * radius_at(p.y), p.z).normalized();
+                hits.push((t, normal, (turns(p.x, p.z), p.y / height)));
+            }
+        }
+    }
+
+    for (y, radius, normal_y) in [(0.0, r0, -1.0), (height, r1, 1.0)] {
+        if radius <= 0.0 || dir.y == 0.0 {
+            continue;
+        }
+        let t = (y - src.y) / dir.y;
+        let p = src + dir * t;
+        if p.x * p.x + p.z * p.z <= radius * radius {
+            let uv = (0.5 + p.x / (2.0 * radius), 0.5 + p.z / (2.0 * radius));
+            hits.push((t, Vec3::new(0.0, normal_y, 0.0), uv));
+        }
+    }
+
+    // Hits on the rim are found on the side and on the cap
+    hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if hits.len() > 2 {
+        hits = vec![hits[0], hits[hits.len() - 1]];
+    }
+    hits
+}
+
+/// A cylinder of finite length, closed by disks at both ends.
+pub struct Cylinder {
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    material: Material,
+}
+
+impl Cylinder {
+    /// Creates a cylinder whose axis goes from the center of the base `base` along `axis` for
+    /// `height`.
+    pub fn new(base: Vec3, axis: Vec3, radius: f64, height: f64, material: Material) -> Cylinder {
+        Cylinder {
+            frame: Frame::new(base, axis),
+            radius,
+            height,
+            material,
+        }
+    }
+
+    fn local_hits(&self, ray: &Ray) -> Vec<LocalHit> {
+        let src = self.frame.to_local(ray.origin());
+        let dir = self.frame.to_local_direction(ray.direction());
+        frustum_hits(&src, &dir, self.radius, self.radius, self.height)
+    }
+}
+
+impl Object3D for Cylinder {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let hit = closest(self.local_hits(ray), ray)?;
+        Some(hit_record(self, hit, |n| self.frame.to_world_direction(&n)))
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let hits = self.local_hits(ray);
+        intervals(self, hits, |n| self.frame.to_world_direction(&n))
+    }
+}
+
+/// A cone of finite length, closed by a disk at its base. With a top radius, it is a truncated
+/// cone that is closed at the top as well.
+pub struct Cone {
+    frame: Frame,
+    base_radius: f64,
+    top_radius: f64,
+    height: f64,
+    material: Material,
+}
+
+impl Cone {
+    /// Creates a cone whose axis goes from the center of the base `base` along `axis` to the
+    /// apex at `height`.
+    pub fn new(base: Vec3, axis: Vec3, radius: f64, height: f64, material: Material) -> Cone {
+        Cone::truncated(base, axis, radius, 0.0, height, material)
+    }
+
+    /// Creates a cone that is cut off at `height`, where its radius is `top_radius`.
+    pub fn truncated(
+        base: Vec3,
+        axis: Vec3,
+        base_radius: f64,
+        top_radius: f64,
+        height: f64,
+        material: Material,
+    ) -> Cone {
+        Cone {
+            frame: Frame::new(base, axis),
+            base_radius,
+            top_radius,
+            height,
+            material,
+        }
+    }
+
+    fn local_hits(&self, ray: &Ray) -> Vec<LocalHit> {
+        let src = self.frame.to_local(ray.origin());
+        let dir = self.frame.to_local_direction(ray.direction());
+        frustum_hits(&src, &dir, self.base_radius, self.top_radius, self.height)
+    }
+}
+
+impl Object3D for Cone {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let hit = closest(self.local_hits(ray), ray)?;
+        Some(hit_record(self, hit, |n| self.frame.to_world_direction(&n)))
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let hits = self.local_hits(ray);
+        intervals(self, hits, |n| self.frame.to_world_direction(&n))
+    }
+}
+
+/// A flat circular disk. Like a plane, it is seen from both sides.
+pub struct Disk {
+    frame: Frame,
+    radius: f64,
+    material: Material,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, material: Material) -> Disk {
+        Disk {
+            frame: Frame::new(center, normal),
+            radius,
+            material,
+        }
+    }
+}
+
+impl Object3D for Disk {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let src = self.frame.to_local(ray.origin());
+        let dir = self.frame.to_local_direction(ray.direction());
+        if dir.y == 0.0 {
+            return None;
+        }
+        let distance = -src.y / dir.y;
+        let p = src + dir * distance;
+        let r = (p.x * p.x + p.z * p.z).sqrt();
+        if !ray.contains(distance) || r > self.radius {
+            return None;
+        }
+        Some(HitRecord {
+            distance,
+            object: self,
+            normal: self.frame.y,
+            // Polar coordinates
+            uv: (turns(p.x, p.z), r / self.radius),
+            color: None,
+            material: None,
+        })
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// A flat parallelogram, usually a rectangle, spanned by two edges from a corner. Like a plane,
+/// it is seen from both sides.
+pub struct Rectangle {
+    corner: Vec3,
+    edge1: Vec3,
+    edge2: Vec3,
+    /// `edge1` x `edge2`
+    normal: Vec3,
+    material: Material,
+}
+
+impl Rectangle {
+    /// The normal of the rectangle is `edge1` x `edge2`.
+    pub fn new(corner: Vec3, edge1: Vec3, edge2: Vec3, material: Material) -> Rectangle {
+        Rectangle {
+            corner,
+            edge1,
+            edge2,
+            normal: edge1.cross(edge2),
+            material,
+        }
+    }
+}
+
+impl Object3D for Rectangle {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let denom = ray.direction().dot(self.normal);
+        if denom == 0.0 {
+            return None;
+        }
+        let distance = (&self.corner - ray.origin()).dot(self.normal) / denom;
+        if !ray.contains(distance) {
+            return None;
+        }
+
+        // Coordinates of the hit along the edges
+        let q = ray.at(distance) - self.corner;
+        let area = self.normal.squared_length();
+        let u = q.cross(self.edge2).dot(self.normal) / area;
+        let v = self.edge1.cross(q).dot(self.normal) / area;
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some(HitRecord {
+            distance,
+            object: self,
+            normal: self.normal.normalized(),
+            uv: (u, v),
+            color: None,
+            material: None,
+        })
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// A ring-shaped surface, swept by a circle with the `minor_radius` whose center moves on a
+/// circle with the `major_radius` around the axis.
+pub struct Torus {
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Material,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: Material,
+    ) -> Torus {
+        Torus {
+            frame: Frame::new(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+
+    /// Intersects the line through the ray with the torus.
+    fn local_hits(&self, ray: &Ray) -> Vec<LocalHit> {
+        let src = self.frame.to_local(ray.origin());
+        let dir = self.frame.to_local_direction(&ray.direction().normalized());
+        let (big_r, small_r) = (self.major_radius, self.minor_radius);
+
+        // Rays that miss the bounding sphere miss the torus. Otherwise the quartic is solved
+        // from where the ray enters the sphere, which keeps its coefficients small and its
+        // roots precise.
+        let bound = big_r + small_r;
+        let b = src.dot(dir);
+        let radicant = b * b - src.squared_length() + bound * bound;
+        if radicant < 0.0 {
+            return Vec::new();
+        }
+        let start = -b - radicant.sqrt();
+        let o = src + dir * start;
+
+        // Points p on the torus satisfy (|p|² + R² - r²)² = 4R²(p.x² + p.z²)
+        let m = o.dot(dir);
+        let q = o.squared_length() + big_r * big_r - small_r * small_r;
+        let four_r2 = 4.0 * big_r * big_r;
+        let roots = solve_quartic(
+            4.0 * m,
+            4.0 * m * m + 2.0 * q - four_r2 * (dir.x * dir.x + dir.z * dir.z),
+            4.0 * m * q - 2.0 * four_r2 * (o.x * dir.x + o.z * dir.z),
+            q * q - four_r2 * (o.x * o.x + o.z * o.z),
+        );
+
+        roots
+            .into_iter()
+            .map(|root| {
+                let t = start + root;
+                let p = src + dir * t;
+                let rho = (p.x * p.x + p.z * p.z).sqrt();
+                // From the center of the tube to the hit
+                let normal =
+                    Vec3::new(p.x - p.x * big_r / rho, p.y, p.z - p.z * big_r / rho).normalized();
+                // Around the axis and around the tube
+                let uv = (turns(p.x, p.z), turns(rho - big_r, p.y));
+                (t / ray.direction().len(), normal, uv)
+            })
+            .collect()
+    }
+}
+
+impl Object3D for Torus {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let hit = closest(self.local_hits(ray), ray)?;
+        Some(hit_record(self, hit, |n| self.frame.to_world_direction(&n)))
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let hits = self.local_hits(ray);
+        intervals(self, hits, |n| self.frame.to_world_direction(&n))
+    }
+}
+
+/// Returns the real roots of a x² + b x + c = 0 in ascending order, a double root twice.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        return Some((-c / b, -c / b));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // Avoids the cancellation of -b + sqrt(discriminant) for small a c
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// Returns the largest real root of x³ + a x² + b x + c = 0.
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let x = if r * r < q * q * q {
+        // Three real roots
+        let theta = (r / (q * q * q).sqrt()).clamp(-1.0, 1.0).acos();
+        [0.0, 2.0 * PI, -2.0 * PI]
+            .map(|offset| -2.0 * q.sqrt() * ((theta + offset) / 3.0).cos() - a / 3.0)
+            .into_iter()
+            .fold(f64::NEG_INFINITY, f64::max)
+    } else {
+        let big_a = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+        let big_b = if big_a == 0.0 { 0.0 } else { q / big_a };
+        big_a + big_b - a / 3.0
+    };
+    // One Newton step against rounding errors
+    let f = ((x + a) * x + b) * x + c;
+    let df = (3.0 * x + 2.0 * a) * x + b;
+    if df != 0.0 {
+        x - f / df
+    } else {
+        x
+    }
+}
+
+/// Returns the real roots of x⁴ + a x³ + b x² + c x + d = 0, using Ferrari's method.
+fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Substituting x = y - a/4 gives the depressed quartic y⁴ + p y² + q y + r = 0
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut roots = Vec::with_capacity(4);
+    if q.abs() < 1E-12 {
+        // Biquadratic: a quadratic in y²
+        if let Some((z0, z1)) = solve_quadratic(1.0, p, r) {
+            for z in [z0, z1] {
+                if z >= 0.0 {
+                    roots.extend([z.sqrt(), -z.sqrt()]);
+                }
+            }
+        }
+    } else {
+        // With a positive root m of the resolvent cubic, the quartic splits into the two
+        // quadratics y² ∓ s y + p/2 + m ± q/(2s) with s = sqrt(2m)
+        let m = largest_cubic_root(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m > 0.0 {
+            let s = (2.0 * m).sqrt();
+            for sign in [1.0, -1.0] {
+                if let Some((y0, y1)) =
+                    solve_quadratic(1.0, -sign * s, p / 2.0 + m + sign * q / (2.0 * s))
+                {
+                    roots.extend([y0, y1]);
+                }
+            }
+        }
+    }
+
+    for x in &mut roots {
+        *x -= a / 4.0;
+        // Polish the root with Newton's method
+        for _ in 0..2 {
+            let f = (((*x + a) * *x + b) * *x + c) * *x + d;
+            let df = ((4.0 * *x + 3.0 * a) * *x + 2.0 * b) * *x + c;
+            if df == 0.0 {
+                break;
+            }
+            *x -= f / df;
+        }
+    }
+    roots
+}
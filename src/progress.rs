@@ -0,0 +1,124 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 40;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Thread-safe progress bar for the render loop. Worker threads report finished tiles, and the
+/// bar (including an ETA and the ray throughput) is redrawn on stderr at most every
+/// `REDRAW_INTERVAL`.
+pub struct Progress {
+    total_tiles: AtomicUsize,
+    done_tiles: AtomicUsize,
+    rays: AtomicU64,
+    start: Instant,
+    last_draw: Mutex<Option<Instant>>,
+    quiet: bool,
+}
+
+impl Progress {
+    pub fn new(quiet: bool) -> Progress {
+        Progress {
+            total_tiles: AtomicUsize::new(0),
+            done_tiles: AtomicUsize::new(0),
+            rays: AtomicU64::new(0),
+            start: Instant::now(),
+            last_draw: Mutex::new(None),
+            quiet,
+        }
+    }
+
+    pub fn set_total(&self, total_tiles: usize) {
+        self.total_tiles.store(total_tiles, Ordering::Relaxed);
+    }
+
+    pub fn tile_done(&self, rays: u64) {
+        self.done_tiles.fetch_add(1, Ordering::Relaxed);
+        self.rays.fetch_add(rays, Ordering::Relaxed);
+
+        if self.quiet {
+            return;
+        }
+
+        // Skip redrawing if another thread is currently drawing or has just done so.
+        if let Ok(mut last_draw) = self.last_draw.try_lock() {
+            let now = Instant::now();
+            if last_draw.is_none_or(|t| now - t >= REDRAW_INTERVAL) {
+                *last_draw = Some(now);
+                self.draw();
+            }
+        }
+    }
+
+    /// Draws the final state of the bar and moves the cursor to the next line.
+    pub fn finish(&self) {
+        if !self.quiet {
+            self.draw();
+            eprintln!();
+        }
+    }
+
+    fn draw(&self) {
+        let done = self.done_tiles.load(Ordering::Relaxed);
+        let total_tiles = self.total_tiles.load(Ordering::Relaxed);
+        let rays = self.rays.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let fraction = if total_tiles == 0 {
+            1.0
+        } else {
+            done as f64 / total_tiles as f64
+        };
+
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let bar: String = (0..BAR_WIDTH)
+            .map(|i| if i < filled { '#' } else { '-' })
+            .collect();
+
+        let eta = if done == 0 {
+            "--".to_string()
+        } else {
+            format_duration(elapsed * (1.0 - fraction) / fraction)
+        };
+
+        let rays_per_sec = if elapsed > 0.0 {
+            rays as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        let mut stderr = std::io::stderr().lock();
+        // Trailing spaces overwrite leftovers of a previous, longer line.
+        let _ = write!(
+            stderr,
+            "\r[{bar}] {:5.1}% {done}/{} tiles, elapsed {}, ETA {eta}, {} rays/s   ",
+            fraction * 100.0,
+            total_tiles,
+            format_duration(elapsed),
+            format_rate(rays_per_sec),
+        );
+        let _ = stderr.flush();
+    }
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, (secs / 60) % 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+fn format_rate(per_sec: f64) -> String {
+    if per_sec >= 1E6 {
+        format!("{:.2}M", per_sec / 1E6)
+    } else if per_sec >= 1E3 {
+        format!("{:.1}k", per_sec / 1E3)
+    } else {
+        format!("{per_sec:.0}")
+    }
+}
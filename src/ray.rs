@@ -0,0 +1,107 @@
+//! Rays as they are passed to the intersection routines of the objects.
+
+use crate::math3d::{gamma, Axis, Vec3};
+
+/// The factor by which the far distances of slab tests are enlarged to cover their rounding
+/// errors, see `Ray::box_interval()`.
+pub(crate) fn far_scale() -> f64 {
+    1.0 + 2.0 * gamma(3)
+}
+
+/// A ray with the interval of distances along it in which hits count. Hits are measured in
+/// units of the length of the direction. The reciprocal of the direction and its signs are
+/// computed once per ray, for slab tests against bounding boxes.
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    origin: Vec3,
+    direction: Vec3,
+    /// Only hits farther than this count, e.g. to skip the surface the ray starts on
+    pub t_min: f64,
+    /// Only hits closer than this count, e.g. because a closer hit is already known
+    pub t_max: f64,
+    /// Moving objects are intersected at their position at this time
+    pub time: f64,
+    /// 1 / direction per coordinate, infinite where the direction is zero
+    inv_direction: Vec3,
+    /// Whether each coordinate of the direction is negative, as an index into [min, max], the
+    /// side of a box at which the ray leaves its slab
+    sign: [usize; 3],
+}
+
+impl Ray {
+    /// A ray along `direction` from `origin`, on which all hits in front of the origin count.
+    pub fn new(origin: Vec3, direction: Vec3, time: f64) -> Ray {
+        let inv_direction = Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        Ray {
+            origin,
+            direction,
+            t_min: 0.0,
+            t_max: f64::INFINITY,
+            time,
+            inv_direction,
+            sign: [
+                (inv_direction.x < 0.0) as usize,
+                (inv_direction.y < 0.0) as usize,
+                (inv_direction.z < 0.0) as usize,
+            ],
+        }
+    }
+
+    /// The same ray, on which only the hits at distances between `t_min` and `t_max` count.
+    pub fn with_interval(self, t_min: f64, t_max: f64) -> Ray {
+        Ray {
+            t_min,
+            t_max,
+            ..self
+        }
+    }
+
+    pub fn origin(&self) -> &Vec3 {
+        &self.origin
+    }
+
+    pub fn direction(&self) -> &Vec3 {
+        &self.direction
+    }
+
+    pub fn inv_direction(&self) -> &Vec3 {
+        &self.inv_direction
+    }
+
+    pub fn sign(&self) -> [usize; 3] {
+        self.sign
+    }
+
+    /// Returns the point at `distance` along the ray.
+    pub fn at(&self, distance: f64) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// Returns whether a hit at `distance` counts.
+    pub fn contains(&self, distance: f64) -> bool {
+        distance > self.t_min && distance < self.t_max
+    }
+
+    /// Returns the part of the ray's interval that is inside the box from `min` to `max`, using
+    /// the slab method. The far distances are enlarged by their rounding error, so that rays
+    /// through the boundary of the box are not missed. Rays along a face of the box may count as
+    /// inside it.
+    pub fn box_interval(&self, min: &Vec3, max: &Vec3) -> Option<(f64, f64)> {
+        let bounds = [min, max];
+        // A NaN, from a ray in the plane of a slab, is ignored by max() and min()
+        let mut t_min = self.t_min;
+        let mut t_max = self.t_max;
+        for (i, axis) in [Axis::X, Axis::Y, Axis::Z].into_iter().enumerate() {
+            let origin = self.origin.get(axis);
+            let inv = self.inv_direction.get(axis);
+            let near = (bounds[self.sign[i]].get(axis) - origin) * inv;
+            let far = (bounds[1 - self.sign[i]].get(axis) - origin) * inv;
+            t_min = t_min.max(near);
+            t_max = t_max.min(far * far_scale());
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
@@ -0,0 +1,304 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Barrier, Mutex};
+
+use crate::aov::{Aov, AovTracer};
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::math3d::Vec3;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::stats::{self, Counter, Stats};
+use crate::tiles::{self, Tile, TileOrder};
+use crate::tracer::{self, AmbientOcclusion, ImageBasedLighting};
+
+/// Parameters of a render that do not depend on the scene or the camera.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// Number of samples per pixel. Every pass renders one sample for each pixel.
+    pub passes: usize,
+    /// Seed from which all random numbers of the render are derived
+    pub seed: u64,
+    /// Number of worker threads, `None` to use one thread per CPU core
+    pub threads: Option<usize>,
+    /// Edge length of the square tiles that are distributed to the worker threads, at least 1
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// Darken the indirect lighting where the geometry occludes it
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    /// Light the objects by the environment instead of a constant ambient light
+    pub image_based_lighting: Option<ImageBasedLighting>,
+    pub mode: RenderMode,
+    /// Additional per-pixel outputs that are rendered into the framebuffer alongside the color
+    pub aovs: Vec<Aov>,
+}
+
+/// What is computed for each pixel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderMode {
+    /// The shaded image
+    Beauty,
+    /// Only the ambient occlusion of the surfaces seen by the camera, using the settings of
+    /// `RenderSettings::ambient_occlusion` or its defaults
+    AmbientOcclusion,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            passes: 1,
+            seed: 0,
+            threads: None,
+            tile_size: 32,
+            tile_order: TileOrder::Hilbert,
+            ambient_occlusion: None,
+            image_based_lighting: None,
+            mode: RenderMode::Beauty,
+            aovs: Vec::new(),
+        }
+    }
+}
+
+/// Callbacks through which the caller of `render_from()` can observe and control a running
+/// render. All methods are called from the worker threads.
+pub trait RenderObserver: Sync {
+    /// Called once before rendering starts, with the number of tiles that will be rendered over
+    /// all passes.
+    fn render_started(&self, _num_tiles: usize) {}
+
+    /// Called after a tile of `pass` has been added to the framebuffer. `rays` is the number of
+    /// rays that were traced to render it.
+    fn tile_done(&self, _pass: usize, _rays: u64, _framebuffer: &Mutex<Framebuffer>) {}
+
+    /// Called once all tiles of `pass` are finished, by a single thread while the others wait.
+    fn pass_done(&self, _pass: usize, _framebuffer: &Mutex<Framebuffer>) {}
+
+    /// Polled regularly. Once it returns true, unfinished tiles are discarded and the render
+    /// stops.
+    fn should_stop(&self) -> bool {
+        false
+    }
+}
+
+/// An observer that does nothing.
+impl RenderObserver for () {}
+
+/// The result of `render_from()`.
+pub struct RenderOutcome {
+    pub framebuffer: Framebuffer,
+    /// The pass during which the render was stopped, or `None` if all passes were completed.
+    /// Rendering can be continued from this pass with the returned framebuffer.
+    pub stopped_in_pass: Option<usize>,
+    /// The statistics of the individual worker threads
+    pub thread_stats: Vec<Stats>,
+}
+
+/// Renders `scene` as seen by `camera` into a new framebuffer, using all CPU cores.
+pub fn render(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Framebuffer {
+    let framebuffer = Framebuffer::with_aovs(camera.width, camera.height, &settings.aovs);
+    render_from(scene, camera, settings, framebuffer, 0, &()).framebuffer
+}
+
+/// Continues a render from pass `first_pass`, adding samples to an existing `framebuffer` of the
+/// camera's size and with the settings' AOVs. Tiles of `first_pass` that already contain samples
+/// of this pass are skipped, so a render that was stopped can be continued with the framebuffer
+/// and pass from its `RenderOutcome`, and yields the same result as an uninterrupted render.
+pub fn render_from(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    framebuffer: Framebuffer,
+    first_pass: usize,
+    observer: &dyn RenderObserver,
+) -> RenderOutcome {
+    assert_eq!(
+        (framebuffer.width(), framebuffer.height()),
+        (camera.width, camera.height),
+        "The framebuffer needs to have the camera's image size"
+    );
+    assert_eq!(
+        framebuffer.aovs(),
+        settings.aovs,
+        "The framebuffer needs to have the settings' AOVs"
+    );
+    assert!(
+        settings.tile_size > 0,
+        "The tile size needs to be at least 1"
+    );
+
+    let tiles = tiles::generate_tiles(
+        camera.width,
+        camera.height,
+        settings.tile_size,
+        settings.tile_order,
+    );
+    // Tiles of the first pass that were already completed before
+    let resumed_tiles: Vec<bool> = tiles
+        .iter()
+        .map(|tile| framebuffer.samples(tile.x, tile.y) as usize > first_pass)
+        .collect();
+    let num_resumed = resumed_tiles.iter().filter(|&&done| done).count();
+    if first_pass < settings.passes {
+        observer.render_started(tiles.len() * (settings.passes - first_pass) - num_resumed);
+    } else {
+        observer.render_started(0);
+    }
+
+    let framebuffer = Mutex::new(framebuffer);
+    let next_tile = AtomicUsize::new(0);
+    let completed_tiles = AtomicUsize::new(num_resumed);
+    let thread_stats = Mutex::new(Vec::new());
+    let stopped_in_pass = Mutex::new(None);
+    // Set by the last thread to finish a pass if the render is to be stopped, so that all
+    // threads agree on whether to stop after the pass.
+    let stop = AtomicBool::new(false);
+
+    let tracer = TileTracer {
+        scene,
+        camera,
+        settings,
+        aov_tracer: AovTracer::new(scene, settings.ambient_occlusion.as_ref()),
+        observer,
+    };
+    let channels = 1 + settings.aovs.len();
+
+    let num_threads = settings.threads.unwrap_or_else(num_cpus::get);
+    let pass_barrier = Barrier::new(num_threads);
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| {
+                let mut colors = Vec::new();
+                for pass in first_pass..settings.passes {
+                    // Threads only move on to the next pass once all tiles of the current pass
+                    // are finished. This way the samples of each pixel are always accumulated
+                    // in the same order, which keeps the results reproducible.
+                    while !observer.should_stop() {
+                        let tile_idx = next_tile.fetch_add(1, Ordering::Relaxed);
+                        let Some(tile) = tiles.get(tile_idx) else {
+                            break;
+                        };
+                        if pass == first_pass && resumed_tiles[tile_idx] {
+                            continue;
+                        }
+
+                        let rays_before = stats::thread_stats().total_rays();
+                        colors.resize(tile.num_pixels() * channels, Vec3::new(0.0, 0.0, 0.0));
+                        if !tracer.trace_tile(tile, pass, &mut colors) {
+                            break;
+                        }
+                        framebuffer.lock().unwrap().add_tile(tile, &colors);
+                        completed_tiles.fetch_add(1, Ordering::Relaxed);
+                        let rays = stats::thread_stats().total_rays() - rays_before;
+                        observer.tile_done(pass, rays, &framebuffer);
+                    }
+
+                    if pass_barrier.wait().is_leader() {
+                        next_tile.store(0, Ordering::Relaxed);
+                        let complete = completed_tiles.swap(0, Ordering::Relaxed) == tiles.len();
+                        if complete {
+                            observer.pass_done(pass, &framebuffer);
+                        }
+                        let is_last = pass + 1 == settings.passes;
+                        if !complete || (!is_last && observer.should_stop()) {
+                            let pass = if complete { pass + 1 } else { pass };
+                            *stopped_in_pass.lock().unwrap() = Some(pass);
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    pass_barrier.wait();
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                thread_stats
+                    .lock()
+                    .unwrap()
+                    .push(stats::take_thread_stats());
+            });
+        }
+    });
+
+    RenderOutcome {
+        framebuffer: framebuffer.into_inner().unwrap(),
+        stopped_in_pass: stopped_in_pass.into_inner().unwrap(),
+        thread_stats: thread_stats.into_inner().unwrap(),
+    }
+}
+
+/// Creates the random number generator for one sample of one pixel. Its seed is derived from the
+/// render's seed, the pixel and the sample index only, so that every sample is computed
+/// identically regardless of the tiling, the number of threads and which thread renders it.
+fn sample_rng(seed: u64, x: usize, y: usize, sample: usize) -> rand::rngs::SmallRng {
+    use rand::SeedableRng;
+    // SplitMix64 finalizer, see https://prng.di.unimi.it/splitmix64.c
+    let mix = |mut z: u64| {
+        z = z.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    };
+    let stream = mix(mix(mix(x as u64) ^ y as u64) ^ sample as u64);
+    rand::rngs::SmallRng::seed_from_u64(mix(seed ^ stream))
+}
+
+/// Everything the worker threads need to render tiles.
+struct TileTracer<'a> {
+    scene: &'a Scene,
+    camera: &'a Camera,
+    settings: &'a RenderSettings,
+    aov_tracer: AovTracer<'a>,
+    observer: &'a dyn RenderObserver,
+}
+
+impl TileTracer<'_> {
+    /// Renders one sample per pixel of a single tile into `colors`, a tightly packed buffer of
+    /// the tile's size that holds the color and the AOVs of each pixel. The first pass samples
+    /// the pixel centers, later passes sample random positions within each pixel to anti-alias
+    /// the image. Returns false if the render was interrupted before the tile was finished.
+    fn trace_tile(&self, tile: &Tile, pass: usize, colors: &mut [Vec3]) -> bool {
+        use rand::Rng;
+        let settings = self.settings;
+        let default_ao = AmbientOcclusion::default();
+        let channels = 1 + settings.aovs.len();
+
+        for y in 0..tile.height {
+            if self.observer.should_stop() {
+                return false;
+            }
+            for x in 0..tile.width {
+                let (x, y) = (tile.x + x, tile.y + y);
+                let mut rng = sample_rng(settings.seed, x, y, pass);
+                let (dx, dy) = if pass == 0 {
+                    (0.5, 0.5)
+                } else {
+                    (rng.gen::<f64>(), rng.gen::<f64>())
+                };
+                let (ray_src, ray_dir) = self.camera.ray(x as f64 + dx, y as f64 + dy, &mut rng);
+                let time = self.camera.time(&mut rng);
+                let idx = ((y - tile.y) * tile.width + (x - tile.x)) * channels;
+                let pixel = &mut colors[idx..idx + channels];
+                // The camera ray is traced once, for both the color and the AOVs
+                stats::count(Counter::PrimaryRays, 1);
+                let ray = Ray::new(ray_src, ray_dir, time);
+                let hit = tracer::trace_ray_with_index(&self.scene.objects, &ray);
+                let closest = hit.as_ref().map(|(_, hit)| hit);
+                pixel[0] = match settings.mode {
+                    RenderMode::Beauty => {
+                        tracer::shade(self.scene, settings, &ray, closest, &mut rng, 0)
+                    }
+                    RenderMode::AmbientOcclusion => {
+                        let ao = settings.ambient_occlusion.as_ref().unwrap_or(&default_ao);
+                        tracer::get_ambient_occlusion(self.scene, ao, &ray, closest, &mut rng)
+                    }
+                };
+                if channels > 1 {
+                    let hit = hit.as_ref().map(|(index, hit)| (*index, hit));
+                    let values = &mut pixel[1..];
+                    self.aov_tracer
+                        .evaluate(&settings.aovs, &ray, hit, &mut rng, values);
+                }
+            }
+        }
+        true
+    }
+}
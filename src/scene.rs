@@ -0,0 +1,40 @@
+use crate::environment::Environment;
+use crate::math3d::Vec3;
+use crate::media::{Fog, Volume};
+use crate::scene_objects::{Object3D, SceneObject};
+
+/// Everything that is rendered: the objects, the light illuminating them, the media between them
+/// and the environment surrounding them.
+pub struct Scene {
+    pub objects: Vec<Box<SceneObject>>,
+    /// Direction towards the (directional) light source, normalized
+    pub light_dir: Vec3,
+    /// What rays that leave the scene see
+    pub environment: Environment,
+    /// Fog filling the space between the objects
+    pub fog: Option<Fog>,
+    pub volumes: Vec<Volume>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
+    }
+}
+
+impl Scene {
+    /// Creates an empty scene, lit from the upper left front and surrounded by blue.
+    pub fn new() -> Scene {
+        Scene {
+            objects: Vec::new(),
+            light_dir: Vec3::new(-1.0, -1.0, -1.0).normalized(),
+            environment: Environment::default(),
+            fog: None,
+            volumes: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, object: impl Object3D + Send + Sync + 'static) {
+        self.objects.push(Box::new(object));
+    }
+}
@@ -1,10 +1,16 @@
-use super::math3d::Vec3;
+use std::sync::Arc;
+
+use super::animation::Keyframes;
+use super::math3d::{Transform, Vec3};
+use super::ray::Ray;
 
 pub struct Material {
     pub color: Vec3,
     pub reflectance: f64,
     pub specular_strength: f64,
     pub specular_exponent: f64,
+    /// Fraction of the light that the surface blocks, the rest passes through it unchanged
+    pub opacity: f64,
 }
 
 impl Material {
@@ -19,6 +25,7 @@ impl Material {
             reflectance,
             specular_strength,
             specular_exponent,
+            opacity: 1.0,
         }
     }
 
@@ -28,6 +35,7 @@ impl Material {
             reflectance: 0.0,
             specular_strength: 0.0,
             specular_exponent: 0.0,
+            opacity: 1.0,
         }
     }
 
@@ -38,13 +46,37 @@ impl Material {
             reflectance: rng.gen::<f64>(),
             specular_strength: rng.gen::<f64>(),
             specular_exponent: rng.gen::<f64>() * 10.0,
+            opacity: 1.0,
         }
     }
 }
 
 pub trait Object3D {
-    fn hit(&self, ray_src: &Vec3, ray_dir: &Vec3) -> Option<HitRecord>;
+    /// Finds the closest intersection with the ray within its interval. Moving objects are
+    /// intersected at their position at the time of the ray.
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>>;
     fn get_material(&self) -> &Material;
+
+    /// Returns whether the ray hits the object within its interval. Unlike `hit()`, this does
+    /// not need to find the closest intersection, so objects consisting of many parts can stop
+    /// at the first one that is hit.
+    fn hit_any(&self, ray: &Ray) -> bool {
+        self.hit(ray).is_some()
+    }
+
+    /// Returns all parts of the ray's line that are inside the object, sorted by distance and
+    /// including those behind the ray origin, which then have negative distances. This is
+    /// needed to combine objects by constructive solid geometry, which is why the interval of
+    /// the ray is ignored. Surfaces that do not enclose a volume, like disks, have no inside and
+    /// return no intervals.
+    fn intervals(&self, _ray: &Ray) -> Vec<Interval<'_>> {
+        Vec::new()
+    }
+
+    /// Returns all materials that hits on the object can have, e.g. to number them.
+    fn materials(&self) -> Vec<&Material> {
+        vec![self.get_material()]
+    }
 }
 
 pub type SceneObject = dyn Object3D + Sync + Send;
@@ -53,10 +85,33 @@ pub struct HitRecord<'a> {
     pub distance: f64,
     pub object: &'a dyn Object3D,
     pub normal: Vec3,
+    /// Surface coordinates of the hit, e.g. for texturing. They are between 0 and 1 on bounded
+    /// surfaces, and distances within the plane on unbounded ones.
+    pub uv: (f64, f64),
+    /// Color of the surface at the hit if it varies over the surface, like vertex colors. It
+    /// replaces the color of the material.
+    pub color: Option<Vec3>,
+    /// Material at the hit if it varies over the object, like the materials of the faces of a
+    /// mesh. It replaces the material of the object.
+    pub material: Option<&'a Material>,
+}
+
+impl<'a> HitRecord<'a> {
+    /// Returns the material at the hit, that of the object unless it varies over the object.
+    pub fn surface_material(&self) -> &'a Material {
+        self.material.unwrap_or_else(|| self.object.get_material())
+    }
+}
+
+/// A part of a ray inside an object, from where the ray enters it to where it leaves it. The
+/// normals at both ends point out of the object.
+pub struct Interval<'a> {
+    pub enter: HitRecord<'a>,
+    pub exit: HitRecord<'a>,
 }
 
 pub struct Sphere {
-    center: Vec3,
+    center: Keyframes<Vec3>,
     radius: f64,
     material: Material,
 }
@@ -69,6 +124,11 @@ pub struct Plane {
 
 impl Sphere {
     pub fn new(center: Vec3, radius: f64, material: Material) -> Sphere {
+        Sphere::moving(Keyframes::constant(center), radius, material)
+    }
+
+    /// A sphere whose center moves over time
+    pub fn moving(center: Keyframes<Vec3>, radius: f64, material: Material) -> Sphere {
         Sphere {
             center,
             radius,
@@ -78,12 +138,13 @@ impl Sphere {
 }
 
 impl Object3D for Sphere {
-    fn hit(&self, ray_src: &Vec3, ray_dir: &Vec3) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let center = self.center.at(ray.time);
         // from https://en.wikipedia.org/wiki/Line%E2%80%93sphere_intersection
-        let oc = ray_src - &self.center;
+        let oc = ray.origin() - &center;
         //let fac = -Vec3::dot(ray_dir, &oc);
         //let dir_dot_oc = Vec3::dot(ray_dir, &oc);
-        let t1 = ray_dir.dot(oc);
+        let t1 = ray.direction().dot(oc);
         let radicant = t1 * t1 - oc.squared_length() + self.radius * self.radius;
 
         if radicant < 0.0 {
@@ -92,24 +153,48 @@ impl Object3D for Sphere {
             let v1 = -t1;
             let v2 = radicant.sqrt();
 
-            if v1 + v2 < 0.0 {
-                // all intersection points lie behind ray_src
-                None
-            } else {
-                let distance = if v1 - v2 >= 0.0 { v1 - v2 } else { v1 + v2 };
-                let hit_point = ray_src + ray_dir * distance;
-                Some(HitRecord {
-                    distance,
-                    object: self,
-                    normal: (hit_point - self.center).normalized(),
-                })
-            }
+            // The closer intersection point, unless it lies outside of the range
+            let distance = [v1 - v2, v1 + v2].into_iter().find(|&t| ray.contains(t))?;
+            Some(self.hit_record(ray, &center, distance))
         }
     }
 
     fn get_material(&self) -> &Material {
         &self.material
     }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let center = self.center.at(ray.time);
+        let oc = ray.origin() - &center;
+        let t1 = ray.direction().dot(oc);
+        let radicant = t1 * t1 - oc.squared_length() + self.radius * self.radius;
+        if radicant < 0.0 {
+            return Vec::new();
+        }
+        let v2 = radicant.sqrt();
+        vec![Interval {
+            enter: self.hit_record(ray, &center, -t1 - v2),
+            exit: self.hit_record(ray, &center, -t1 + v2),
+        }]
+    }
+}
+
+impl Sphere {
+    fn hit_record(&self, ray: &Ray, center: &Vec3, distance: f64) -> HitRecord<'_> {
+        let hit_point = ray.at(distance);
+        let normal = (hit_point - *center).normalized();
+        // Longitude and latitude, starting at the top
+        let u = 0.5 + normal.x.atan2(normal.z) / (2.0 * std::f64::consts::PI);
+        let v = (-normal.y).clamp(-1.0, 1.0).acos() / std::f64::consts::PI;
+        HitRecord {
+            distance,
+            object: self,
+            normal,
+            uv: (u, v),
+            color: None,
+            material: None,
+        }
+    }
 }
 
 impl Plane {
@@ -123,24 +208,173 @@ impl Plane {
 }
 
 impl Object3D for Plane {
-    fn hit(&self, ray_src: &Vec3, ray_dir: &Vec3) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
         //from https://en.wikipedia.org/wiki/Line%E2%80%93plane_intersection
-        let denom = ray_dir.dot(self.normal);
-        let num = (&self.point - ray_src).dot(self.normal);
+        let denom = ray.direction().dot(self.normal);
+        let num = (&self.point - ray.origin()).dot(self.normal);
         //println!("test plane with {}/{}", num, denom);
-        // denom is zero or num and denom differ in sign --> quotient would be negative
-        if denom == 0.0 || num * denom < 0.0 {
-            None
+        if denom == 0.0 {
+            return None;
+        }
+        let distance = num / denom;
+        if ray.contains(distance) {
+            Some(self.hit_record(ray, distance))
         } else {
-            Some(HitRecord {
-                distance: num / denom,
-                object: self,
-                normal: self.normal,
-            })
+            None
         }
     }
 
     fn get_material(&self) -> &Material {
         &self.material
     }
+
+    /// For constructive solid geometry, a plane is the half-space behind its normal. Its
+    /// intervals end at infinity on the side of the half-space. Those ends are not on the plane
+    /// and cannot be hit, as no ray interval contains an infinite distance, so their records
+    /// only carry the distance.
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let denom = ray.direction().dot(self.normal);
+        let num = (&self.point - ray.origin()).dot(self.normal);
+        let (enter, exit) = if denom == 0.0 {
+            if num < 0.0 {
+                // Parallel to the plane and in front of it
+                return Vec::new();
+            }
+            (f64::NEG_INFINITY, f64::INFINITY)
+        } else if denom < 0.0 {
+            (num / denom, f64::INFINITY)
+        } else {
+            (f64::NEG_INFINITY, num / denom)
+        };
+        vec![Interval {
+            enter: self.hit_record(ray, enter),
+            exit: self.hit_record(ray, exit),
+        }]
+    }
+}
+
+impl Plane {
+    fn hit_record(&self, ray: &Ray, distance: f64) -> HitRecord<'_> {
+        // The ends of intervals at infinity have no position within the plane
+        let uv = if distance.is_finite() {
+            let (u_axis, v_axis) = self.normal.normalized().perpendicular_axes();
+            let p = ray.at(distance) - self.point;
+            (p.dot(u_axis), p.dot(v_axis))
+        } else {
+            (0.0, 0.0)
+        };
+        HitRecord {
+            distance,
+            object: self,
+            normal: self.normal,
+            uv,
+            color: None,
+            material: None,
+        }
+    }
+}
+
+/// Places a shared object into the scene with a (possibly animated) transform. The same object,
+/// e.g. a large mesh, can be instanced any number of times without copying it.
+pub struct Instance {
+    object: Arc<SceneObject>,
+    transform: Keyframes<Transform>,
+    /// Replaces the material of the instanced object
+    material: Option<Material>,
+}
+
+impl Instance {
+    pub fn new(object: Arc<SceneObject>, transform: Keyframes<Transform>) -> Instance {
+        Instance {
+            object,
+            transform,
+            material: None,
+        }
+    }
+
+    /// Renders this instance with `material` instead of the object's own material.
+    pub fn with_material(self, material: Material) -> Instance {
+        Instance {
+            material: Some(material),
+            ..self
+        }
+    }
+}
+
+impl Instance {
+    /// Transforms the ray into the space of the instanced object, with a normalized direction.
+    /// Returns it and the factor that converts distances along the transformed ray into
+    /// distances along the original one.
+    fn to_object_space(&self, ray: &Ray, transform: &Transform) -> (Ray, f64) {
+        let src = transform.inverse_transform_point(*ray.origin());
+        let dir = transform.inverse_transform_direction(*ray.direction());
+        let scale = 1.0 / dir.len();
+        let object_ray = Ray::new(src, dir * scale, ray.time)
+            .with_interval(ray.t_min / scale, ray.t_max / scale);
+        (object_ray, scale)
+    }
+
+    /// Transforms a hit of the ray returned by `to_object_space()` back into the scene.
+    fn to_world_space<'a>(
+        &'a self,
+        hit: HitRecord<'a>,
+        transform: &Transform,
+        scale: f64,
+    ) -> HitRecord<'a> {
+        // The material of the instance replaces the surface properties of the object
+        let (object, color, material): (&dyn Object3D, _, _) = if self.material.is_some() {
+            (self, None, None)
+        } else {
+            (hit.object, hit.color, hit.material)
+        };
+        HitRecord {
+            distance: hit.distance * scale,
+            object,
+            normal: transform.rotation.rotate(hit.normal),
+            uv: hit.uv,
+            color,
+            material,
+        }
+    }
+}
+
+impl Object3D for Instance {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let transform = self.transform.at(ray.time);
+        let (object_ray, scale) = self.to_object_space(ray, &transform);
+        let hit = self.object.hit(&object_ray)?;
+        Some(self.to_world_space(hit, &transform, scale))
+    }
+
+    fn get_material(&self) -> &Material {
+        self.material
+            .as_ref()
+            .unwrap_or_else(|| self.object.get_material())
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        match &self.material {
+            Some(material) => vec![material],
+            None => self.object.materials(),
+        }
+    }
+
+    fn hit_any(&self, ray: &Ray) -> bool {
+        let transform = self.transform.at(ray.time);
+        let (object_ray, _) = self.to_object_space(ray, &transform);
+        self.object.hit_any(&object_ray)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+        let transform = self.transform.at(ray.time);
+        let (object_ray, scale) = self.to_object_space(ray, &transform);
+        self.object
+            .intervals(&object_ray)
+            .into_iter()
+            .map(|interval| Interval {
+                enter: self.to_world_space(interval.enter, &transform, scale),
+                exit: self.to_world_space(interval.exit, &transform, scale),
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,361 @@
+//! Shapes described by signed distance functions (SDFs), which return the distance from a point
+//! to the surface, negative inside. They are easily combined, blended smoothly and deformed, and
+//! describe fractals that have no other closed form. `SdfObject` renders them by sphere tracing.
+
+use crate::math3d::{Rotation, Vec3};
+use crate::ray::Ray;
+use crate::scene_objects::{HitRecord, Material, Object3D};
+
+/// A signed distance function. The distance may be underestimated, which only slows down sphere
+/// tracing, but never overestimated, or rays would step through the surface.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, p: Vec3) -> f64;
+
+    /// Everything inside `self` or `other`
+    fn union<B: Sdf>(self, other: B) -> Union<Self, B>
+    where
+        Self: Sized,
+    {
+        Union(self, other)
+    }
+
+    /// Everything inside both `self` and `other`
+    fn intersection<B: Sdf>(self, other: B) -> Intersection<Self, B>
+    where
+        Self: Sized,
+    {
+        Intersection(self, other)
+    }
+
+    /// Everything inside `self` but not inside `other`
+    fn difference<B: Sdf>(self, other: B) -> Difference<Self, B>
+    where
+        Self: Sized,
+    {
+        Difference(self, other)
+    }
+
+    /// Union that blends both shapes where they are closer than `k` to each other
+    fn smooth_union<B: Sdf>(self, other: B, k: f64) -> SmoothUnion<Self, B>
+    where
+        Self: Sized,
+    {
+        SmoothUnion(self, other, k)
+    }
+
+    /// Grows the shape by `radius`, rounding its edges
+    fn rounded(self, radius: f64) -> Rounded<Self>
+    where
+        Self: Sized,
+    {
+        Rounded(self, radius)
+    }
+
+    fn translated(self, offset: Vec3) -> Translated<Self>
+    where
+        Self: Sized,
+    {
+        Translated(self, offset)
+    }
+
+    fn rotated(self, rotation: Rotation) -> Rotated<Self>
+    where
+        Self: Sized,
+    {
+        Rotated(self, rotation.inverse())
+    }
+
+    fn scaled(self, scale: f64) -> Scaled<Self>
+    where
+        Self: Sized,
+    {
+        Scaled(self, scale)
+    }
+}
+
+impl<F: Fn(Vec3) -> f64 + Send + Sync> Sdf for F {
+    fn distance(&self, p: Vec3) -> f64 {
+        self(p)
+    }
+}
+
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f64,
+}
+
+impl Sdf for Sphere {
+    fn distance(&self, p: Vec3) -> f64 {
+        (p - self.center).len() - self.radius
+    }
+}
+
+/// An axis-aligned box around the origin, extending `half_size` in both directions of each axis
+pub struct Cuboid {
+    pub half_size: Vec3,
+}
+
+impl Sdf for Cuboid {
+    fn distance(&self, p: Vec3) -> f64 {
+        let q = Vec3::new(
+            p.x.abs() - self.half_size.x,
+            p.y.abs() - self.half_size.y,
+            p.z.abs() - self.half_size.z,
+        );
+        let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).len();
+        let inside = q.x.max(q.y).max(q.z).min(0.0);
+        outside + inside
+    }
+}
+
+/// A torus around the y axis through the origin
+pub struct Torus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+
+impl Sdf for Torus {
+    fn distance(&self, p: Vec3) -> f64 {
+        let rho = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+        (rho * rho + p.y * p.y).sqrt() - self.minor_radius
+    }
+}
+
+/// All points within `radius` of the line segment from `a` to `b`
+pub struct Capsule {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub radius: f64,
+}
+
+impl Sdf for Capsule {
+    fn distance(&self, p: Vec3) -> f64 {
+        let pa = p - self.a;
+        let ba = self.b - self.a;
+        let h = (pa.dot(ba) / ba.squared_length()).clamp(0.0, 1.0);
+        (pa - ba * h).len() - self.radius
+    }
+}
+
+/// The Mandelbulb fractal around the origin, about 1.2 in radius. Higher `iterations` give more
+/// detail at a higher cost per step.
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: u32,
+}
+
+impl Mandelbulb {
+    /// The classic bulb of power 8
+    pub fn new() -> Mandelbulb {
+        Mandelbulb {
+            power: 8.0,
+            iterations: 12,
+        }
+    }
+}
+
+impl Default for Mandelbulb {
+    fn default() -> Self {
+        Mandelbulb::new()
+    }
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: Vec3) -> f64 {
+        // The distance estimate overestimates far from the fractal, use a bounding sphere there
+        let len = p.len();
+        if len > 2.0 {
+            return len - 1.5;
+        }
+
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = len;
+        for _ in 0..self.iterations {
+            r = z.len();
+            if r > 2.0 {
+                break;
+            }
+            // Raise z to the power in spherical coordinates and add p
+            let theta = (z.z / r).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ) * zr
+                + p;
+        }
+        0.5 * r.ln() * r / dr
+    }
+}
+
+pub struct Union<A, B>(A, B);
+
+impl<A: Sdf, B: Sdf> Sdf for Union<A, B> {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.0.distance(p).min(self.1.distance(p))
+    }
+}
+
+pub struct Intersection<A, B>(A, B);
+
+impl<A: Sdf, B: Sdf> Sdf for Intersection<A, B> {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.0.distance(p).max(self.1.distance(p))
+    }
+}
+
+pub struct Difference<A, B>(A, B);
+
+impl<A: Sdf, B: Sdf> Sdf for Difference<A, B> {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.0.distance(p).max(-self.1.distance(p))
+    }
+}
+
+pub struct SmoothUnion<A, B>(A, B, f64);
+
+impl<A: Sdf, B: Sdf> Sdf for SmoothUnion<A, B> {
+    fn distance(&self, p: Vec3) -> f64 {
+        // Polynomial smooth minimum
+        let (a, b, k) = (self.0.distance(p), self.1.distance(p), self.2);
+        let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+        b + (a - b) * h - k * h * (1.0 - h)
+    }
+}
+
+pub struct Rounded<A>(A, f64);
+
+impl<A: Sdf> Sdf for Rounded<A> {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.0.distance(p) - self.1
+    }
+}
+
+pub struct Translated<A>(A, Vec3);
+
+impl<A: Sdf> Sdf for Translated<A> {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.0.distance(p - self.1)
+    }
+}
+
+/// Holds the inverse rotation, which maps points back onto the original shape.
+pub struct Rotated<A>(A, Rotation);
+
+impl<A: Sdf> Sdf for Rotated<A> {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.0.distance(self.1.rotate(p))
+    }
+}
+
+pub struct Scaled<A>(A, f64);
+
+impl<A: Sdf> Sdf for Scaled<A> {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.0.distance(p * (1.0 / self.1)) * self.1
+    }
+}
+
+/// An object whose surface is the zero set of a signed distance function, found by sphere
+/// tracing: the ray advances by the distance to the surface until it is closer than `epsilon`.
+pub struct SdfObject {
+    sdf: Box<dyn Sdf>,
+    material: Material,
+    max_steps: u32,
+    epsilon: f64,
+    max_distance: f64,
+}
+
+impl SdfObject {
+    pub fn new(sdf: impl Sdf + 'static, material: Material) -> SdfObject {
+        SdfObject {
+            sdf: Box::new(sdf),
+            material,
+            max_steps: 256,
+            epsilon: 1E-3,
+            max_distance: 1E4,
+        }
+    }
+
+    /// Rays that have not hit the surface after this many steps miss it. Fractals and surfaces
+    /// seen at grazing angles need more steps.
+    pub fn with_max_steps(self, max_steps: u32) -> SdfObject {
+        SdfObject { max_steps, ..self }
+    }
+
+    /// Distance to the surface at which it counts as hit. It is also the step size of the
+    /// gradient estimation for normals.
+    pub fn with_epsilon(self, epsilon: f64) -> SdfObject {
+        SdfObject { epsilon, ..self }
+    }
+
+    /// Rays that travel this far without hitting the surface miss it.
+    pub fn with_max_distance(self, max_distance: f64) -> SdfObject {
+        SdfObject {
+            max_distance,
+            ..self
+        }
+    }
+
+    /// Estimates the normal as the gradient of the distance function, from central differences
+    /// at the vertices of a tetrahedron.
+    fn normal(&self, p: Vec3) -> Vec3 {
+        let h = self.epsilon;
+        [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ]
+        .into_iter()
+        .fold(Vec3::new(0.0, 0.0, 0.0), |sum, k| {
+            sum + k * self.sdf.distance(p + k * h)
+        })
+        .normalized()
+    }
+}
+
+impl Object3D for SdfObject {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        let len = ray.direction().len();
+        let dir = ray.direction() * (1.0 / len);
+        let max_distance = self.max_distance.min(ray.t_max * len);
+
+        // Rays starting on the surface, like reflections and shadow rays, must leave it before
+        // they can hit it
+        let mut left_surface = false;
+        let mut t = ray.t_min.max(0.0) * len;
+        for _ in 0..self.max_steps {
+            let p = ray.origin() + dir * t;
+            let distance = self.sdf.distance(p);
+            if distance < self.epsilon {
+                if left_surface {
+                    return Some(HitRecord {
+                        distance: t / len,
+                        object: self,
+                        normal: self.normal(p),
+                        // Distance functions have no parametrization
+                        uv: (0.0, 0.0),
+                        color: None,
+                        material: None,
+                    });
+                }
+            } else {
+                left_surface = true;
+            }
+            t += distance.abs().max(self.epsilon);
+            if t > max_distance {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
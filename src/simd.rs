@@ -0,0 +1,305 @@
+//! Intersection kernels that test a ray against four bounding boxes or four triangles at once.
+//! They use AVX where the CPU supports it, and otherwise the scalar code lane by lane. Both paths
+//! perform the same floating point operations in the same order, so their results are identical.
+
+use crate::math3d::Vec3;
+use crate::mesh::{intersect_line, ShearedRay};
+use crate::ray::Ray;
+
+/// Distance, barycentric coordinates and rounding error of the distance of a hit of a triangle,
+/// as returned by the triangle kernel
+pub type TriangleHit = (f64, f64, f64, f64);
+
+/// Four axis-aligned boxes, stored by coordinate so that each coordinate of all boxes is loaded
+/// at once.
+#[derive(Debug, Clone)]
+pub struct Boxes4 {
+    /// The minimum corners, by axis and box
+    min: [[f64; 4]; 3],
+    /// The maximum corners, by axis and box
+    max: [[f64; 4]; 3],
+}
+
+impl Boxes4 {
+    /// Stores the boxes given by their minimum and maximum corners. Missing boxes are empty, no
+    /// ray hits them.
+    pub fn new(boxes: &[(Vec3, Vec3)]) -> Boxes4 {
+        assert!(boxes.len() <= 4, "Boxes4 holds at most four boxes");
+        let mut result = Boxes4 {
+            min: [[f64::INFINITY; 4]; 3],
+            max: [[f64::NEG_INFINITY; 4]; 3],
+        };
+        for (lane, (min, max)) in boxes.iter().enumerate() {
+            for (axis, (min, max)) in [(min.x, max.x), (min.y, max.y), (min.z, max.z)]
+                .into_iter()
+                .enumerate()
+            {
+                result.min[axis][lane] = min;
+                result.max[axis][lane] = max;
+            }
+        }
+        result
+    }
+
+    fn corners(&self, lane: usize) -> (Vec3, Vec3) {
+        let corner = |c: &[[f64; 4]; 3]| Vec3::new(c[0][lane], c[1][lane], c[2][lane]);
+        (corner(&self.min), corner(&self.max))
+    }
+}
+
+/// Four triangles, stored by vertex and coordinate so that each coordinate of a vertex of all
+/// triangles is loaded at once.
+#[derive(Debug, Clone)]
+pub struct Triangles4 {
+    /// The coordinates by vertex, axis and triangle
+    vertices: [[[f64; 4]; 3]; 3],
+}
+
+impl Triangles4 {
+    /// Stores the triangles given by their vertices.
+    pub fn new(triangles: &[[Vec3; 3]; 4]) -> Triangles4 {
+        let mut vertices = [[[0.0; 4]; 3]; 3];
+        for (lane, triangle) in triangles.iter().enumerate() {
+            for (vertex, v) in triangle.iter().enumerate() {
+                vertices[vertex][0][lane] = v.x;
+                vertices[vertex][1][lane] = v.y;
+                vertices[vertex][2][lane] = v.z;
+            }
+        }
+        Triangles4 { vertices }
+    }
+
+    fn triangle(&self, lane: usize) -> [Vec3; 3] {
+        self.vertices
+            .map(|v| Vec3::new(v[0][lane], v[1][lane], v[2][lane]))
+    }
+}
+
+/// The intersection kernels available on this CPU. Using AVX is only possible when it is
+/// detected at runtime, so that the binary still runs on CPUs without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kernels {
+    avx: bool,
+}
+
+impl Kernels {
+    /// The fastest kernels that the CPU supports.
+    pub fn detect() -> Kernels {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx") {
+            return Kernels { avx: true };
+        }
+        Kernels::scalar()
+    }
+
+    /// The scalar code, e.g. to compare the results of the SIMD kernels with.
+    pub fn scalar() -> Kernels {
+        Kernels { avx: false }
+    }
+
+    pub fn uses_avx(&self) -> bool {
+        self.avx
+    }
+
+    /// Returns for each box the distance at which the ray enters it within its interval, or
+    /// `None` if the ray misses it, see `Ray::box_interval()`.
+    pub fn hit_boxes(&self, ray: &Ray, boxes: &Boxes4) -> [Option<f64>; 4] {
+        #[cfg(target_arch = "x86_64")]
+        if self.avx {
+            // Safety: AVX was detected when creating the kernels
+            return unsafe { avx::hit_boxes(ray, boxes) };
+        }
+        std::array::from_fn(|lane| {
+            let (min, max) = boxes.corners(lane);
+            ray.box_interval(&min, &max).map(|(near, _)| near)
+        })
+    }
+
+    /// Intersects the line through the ray with each of the triangles, like
+    /// `intersect_triangles_sheared()`.
+    pub fn intersect_triangles(
+        &self,
+        ray: &Ray,
+        triangles: &Triangles4,
+    ) -> [Option<TriangleHit>; 4] {
+        self.intersect_triangles_sheared(&ShearedRay::new(ray), triangles)
+    }
+
+    /// Intersects the line through the ray with each of the triangles watertightly. Returns the
+    /// distance along the ray, negative behind its origin, the barycentric coordinates with
+    /// respect to the second and third vertex, and the rounding error of the distance.
+    pub(crate) fn intersect_triangles_sheared(
+        &self,
+        ray: &ShearedRay,
+        triangles: &Triangles4,
+    ) -> [Option<TriangleHit>; 4] {
+        #[cfg(target_arch = "x86_64")]
+        if self.avx {
+            // Safety: AVX was detected when creating the kernels
+            return unsafe { avx::intersect_triangles(ray, triangles) };
+        }
+        std::array::from_fn(|lane| intersect_line(triangles.triangle(lane), ray))
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx {
+    //! The kernels with four f64 lanes. Where the scalar code ignores NaNs with `f64::max()` and
+    //! `f64::min()`, `_mm256_max_pd()` and `_mm256_min_pd()` take the value that is not NaN as
+    //! their second operand, which they return if the first one is NaN.
+
+    use std::arch::x86_64::*;
+
+    use super::{Boxes4, TriangleHit, Triangles4};
+    use crate::math3d::gamma;
+    use crate::mesh::ShearedRay;
+    use crate::ray::{far_scale, Ray};
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn hit_boxes(ray: &Ray, boxes: &Boxes4) -> [Option<f64>; 4] {
+        let origin = ray.origin();
+        let inv_direction = ray.inv_direction();
+        let origin = [origin.x, origin.y, origin.z];
+        let inv_direction = [inv_direction.x, inv_direction.y, inv_direction.z];
+        let sign = ray.sign();
+
+        let far_scale = _mm256_set1_pd(far_scale());
+        let mut t_min = _mm256_set1_pd(ray.t_min);
+        let mut t_max = _mm256_set1_pd(ray.t_max);
+        for axis in 0..3 {
+            let (near_bounds, far_bounds) = if sign[axis] == 0 {
+                (&boxes.min[axis], &boxes.max[axis])
+            } else {
+                (&boxes.max[axis], &boxes.min[axis])
+            };
+            let origin = _mm256_set1_pd(origin[axis]);
+            let inv = _mm256_set1_pd(inv_direction[axis]);
+            let near = _mm256_mul_pd(_mm256_sub_pd(load(near_bounds), origin), inv);
+            let far = _mm256_mul_pd(_mm256_sub_pd(load(far_bounds), origin), inv);
+            t_min = _mm256_max_pd(near, t_min);
+            t_max = _mm256_min_pd(_mm256_mul_pd(far, far_scale), t_max);
+        }
+        let mask = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_LE_OQ>(t_min, t_max));
+        let near = store(t_min);
+        std::array::from_fn(|lane| (mask & (1 << lane) != 0).then_some(near[lane]))
+    }
+
+    /// Follows `mesh::intersect_line()` operation by operation.
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn intersect_triangles(
+        ray: &ShearedRay,
+        triangles: &Triangles4,
+    ) -> [Option<TriangleHit>; 4] {
+        let [kx, ky, kz] = ray.axes;
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z].map(|o| _mm256_set1_pd(o));
+        let [shear_x, shear_y, scale_z] = ray.shear.map(|s| _mm256_set1_pd(s));
+
+        // The vertices in the space of the ray
+        let [a, b, c] = triangles.vertices.map(|vertex| {
+            let p = [0, 1, 2].map(|axis| _mm256_sub_pd(load(&vertex[axis]), origin[axis]));
+            [
+                _mm256_add_pd(p[kx], _mm256_mul_pd(shear_x, p[kz])),
+                _mm256_add_pd(p[ky], _mm256_mul_pd(shear_y, p[kz])),
+                p[kz],
+            ]
+        });
+
+        let edge = |p: &[__m256d; 3], q: &[__m256d; 3]| {
+            _mm256_sub_pd(_mm256_mul_pd(p[0], q[1]), _mm256_mul_pd(p[1], q[0]))
+        };
+        let e0 = edge(&b, &c);
+        let e1 = edge(&c, &a);
+        let e2 = edge(&a, &b);
+        let zero = _mm256_setzero_pd();
+        let [negative0, negative1, negative2] =
+            [e0, e1, e2].map(|e| _mm256_cmp_pd::<_CMP_LT_OQ>(e, zero));
+        let [positive0, positive1, positive2] =
+            [e0, e1, e2].map(|e| _mm256_cmp_pd::<_CMP_GT_OQ>(e, zero));
+        let any_negative = _mm256_or_pd(_mm256_or_pd(negative0, negative1), negative2);
+        let any_positive = _mm256_or_pd(_mm256_or_pd(positive0, positive1), positive2);
+        let outside = _mm256_and_pd(any_negative, any_positive);
+        let det = _mm256_add_pd(_mm256_add_pd(e0, e1), e2);
+        let parallel = _mm256_cmp_pd::<_CMP_EQ_OQ>(det, zero);
+        let missed = _mm256_movemask_pd(_mm256_or_pd(outside, parallel));
+        if missed == 0b1111 {
+            return [None; 4];
+        }
+
+        let [az, bz, cz] = [a[2], b[2], c[2]].map(|z| _mm256_mul_pd(z, scale_z));
+        let inv_det = _mm256_div_pd(_mm256_set1_pd(1.0), det);
+        let t = _mm256_mul_pd(
+            _mm256_add_pd(
+                _mm256_add_pd(_mm256_mul_pd(e0, az), _mm256_mul_pd(e1, bz)),
+                _mm256_mul_pd(e2, cz),
+            ),
+            inv_det,
+        );
+
+        let max_abs = |values: [__m256d; 3]| {
+            values
+                .into_iter()
+                .fold(zero, |m, v| _mm256_max_pd(abs(v), m))
+        };
+        let max_x = max_abs([a[0], b[0], c[0]]);
+        let max_y = max_abs([a[1], b[1], c[1]]);
+        let max_z = max_abs([az, bz, cz]);
+        let max_e = max_abs([e0, e1, e2]);
+        let [gamma2, gamma3, gamma5] = [2, 3, 5].map(|n| _mm256_set1_pd(gamma(n)));
+        let delta_x = _mm256_mul_pd(gamma5, _mm256_add_pd(max_x, max_z));
+        let delta_y = _mm256_mul_pd(gamma5, _mm256_add_pd(max_y, max_z));
+        let delta_z = _mm256_mul_pd(gamma3, max_z);
+        let delta_e = _mm256_mul_pd(
+            _mm256_set1_pd(2.0),
+            _mm256_add_pd(
+                _mm256_add_pd(
+                    _mm256_mul_pd(_mm256_mul_pd(gamma2, max_x), max_y),
+                    _mm256_mul_pd(delta_y, max_x),
+                ),
+                _mm256_mul_pd(delta_x, max_y),
+            ),
+        );
+        let delta_t = _mm256_mul_pd(
+            _mm256_mul_pd(
+                _mm256_set1_pd(3.0),
+                _mm256_add_pd(
+                    _mm256_add_pd(
+                        _mm256_mul_pd(_mm256_mul_pd(gamma3, max_e), max_z),
+                        _mm256_mul_pd(delta_e, max_z),
+                    ),
+                    _mm256_mul_pd(delta_z, max_e),
+                ),
+            ),
+            abs(inv_det),
+        );
+
+        let length = _mm256_set1_pd(ray.direction_length);
+        let distance = store(_mm256_mul_pd(t, length));
+        let u = store(_mm256_mul_pd(e1, inv_det));
+        let v = store(_mm256_mul_pd(e2, inv_det));
+        let error = store(_mm256_mul_pd(delta_t, length));
+        std::array::from_fn(|lane| {
+            (missed & (1 << lane) == 0).then_some((distance[lane], u[lane], v[lane], error[lane]))
+        })
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn load(values: &[f64; 4]) -> __m256d {
+        _mm256_loadu_pd(values.as_ptr())
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn store(values: __m256d) -> [f64; 4] {
+        let mut result = [0.0; 4];
+        _mm256_storeu_pd(result.as_mut_ptr(), values);
+        result
+    }
+
+    /// Clears the sign bits
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn abs(values: __m256d) -> __m256d {
+        _mm256_andnot_pd(_mm256_set1_pd(-0.0), values)
+    }
+}
@@ -0,0 +1,182 @@
+use std::cell::Cell;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Events that are counted during a render.
+#[derive(Debug, Copy, Clone)]
+pub enum Counter {
+    /// Rays cast from the camera
+    PrimaryRays,
+    /// Reflection rays
+    SecondaryRays,
+    /// Visibility-only rays, e.g. for ambient occlusion
+    ShadowRays,
+    /// Ray/object intersection tests performed by `trace_ray`
+    ObjectTests,
+    /// Ray/triangle intersection tests performed inside of meshes
+    TriangleTests,
+    /// Nodes of the bounding volume hierarchies of meshes whose child boxes were tested
+    BvhNodes,
+}
+
+const NUM_COUNTERS: usize = 6;
+const COUNTER_NAMES: [&str; NUM_COUNTERS] = [
+    "primary_rays",
+    "secondary_rays",
+    "shadow_rays",
+    "object_tests",
+    "triangle_tests",
+    "bvh_nodes",
+];
+
+/// Distinct phases of a program run whose wall-clock time is measured.
+#[derive(Debug, Copy, Clone)]
+pub enum Phase {
+    /// Building the scene, including parsing the mesh files
+    SceneBuild,
+    MeshParse,
+    Render,
+    Denoise,
+    Encode,
+}
+
+const NUM_PHASES: usize = 5;
+const PHASE_NAMES: [&str; NUM_PHASES] =
+    ["scene_build", "mesh_parse", "render", "denoise", "encode"];
+
+/// A set of event counters and phase timings.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stats {
+    counters: [u64; NUM_COUNTERS],
+    phases: [Duration; NUM_PHASES],
+}
+
+thread_local! {
+    // Every thread counts into its own set of counters, so that counting does not need any
+    // synchronization. The counters are collected with `take_thread_stats()`.
+    static THREAD_STATS: Cell<Stats> = const {
+        Cell::new(Stats {
+            counters: [0; NUM_COUNTERS],
+            phases: [Duration::ZERO; NUM_PHASES],
+        })
+    };
+}
+
+/// Adds `n` to the given counter of the current thread.
+pub fn count(counter: Counter, n: u64) {
+    THREAD_STATS.with(|stats| {
+        let mut s = stats.get();
+        s.counters[counter as usize] += n;
+        stats.set(s);
+    });
+}
+
+/// Runs `f` and adds its wall-clock duration to the given phase of the current thread.
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    THREAD_STATS.with(|stats| {
+        let mut s = stats.get();
+        s.phases[phase as usize] += elapsed;
+        stats.set(s);
+    });
+    result
+}
+
+/// Returns the current thread's statistics without resetting them.
+pub fn thread_stats() -> Stats {
+    THREAD_STATS.with(|stats| stats.get())
+}
+
+/// Returns the current thread's statistics and resets them to zero.
+pub fn take_thread_stats() -> Stats {
+    THREAD_STATS.with(|stats| stats.replace(Stats::default()))
+}
+
+impl Stats {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize]
+    }
+
+    pub fn get_time(&self, phase: Phase) -> Duration {
+        self.phases[phase as usize]
+    }
+
+    pub fn total_rays(&self) -> u64 {
+        self.get(Counter::PrimaryRays)
+            + self.get(Counter::SecondaryRays)
+            + self.get(Counter::ShadowRays)
+    }
+
+    pub fn merge(&mut self, other: &Stats) {
+        for (c, o) in self.counters.iter_mut().zip(other.counters) {
+            *c += o;
+        }
+        for (p, o) in self.phases.iter_mut().zip(other.phases) {
+            *p += o;
+        }
+    }
+}
+
+/// Statistics of a whole program run: the totals and the counters of the individual worker
+/// threads.
+pub struct Report {
+    pub total: Stats,
+    pub threads: Vec<Stats>,
+}
+
+impl Report {
+    /// Human-readable multi-line summary
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let render_secs = self.total.get_time(Phase::Render).as_secs_f64();
+
+        writeln!(text, "Render statistics:").unwrap();
+        for (name, value) in COUNTER_NAMES.iter().zip(self.total.counters) {
+            writeln!(text, "  {:<16} {value:>16}", name.replace('_', " ")).unwrap();
+        }
+        if render_secs > 0.0 {
+            let rate = self.total.total_rays() as f64 / render_secs;
+            writeln!(text, "  {:<16} {:>16.0}", "rays per second", rate).unwrap();
+        }
+        writeln!(text, "Timings:").unwrap();
+        for (name, value) in PHASE_NAMES.iter().zip(self.total.phases) {
+            let name = name.replace('_', " ");
+            writeln!(text, "  {name:<16} {:>15.3}s", value.as_secs_f64()).unwrap();
+        }
+        text
+    }
+
+    /// The statistics as a JSON object, for automated performance tracking
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n");
+        json += &format!("  \"total\": {},\n", stats_to_json(&self.total, "  "));
+        json += "  \"threads\": [";
+        for (i, thread) in self.threads.iter().enumerate() {
+            json += if i == 0 { "\n    " } else { ",\n    " };
+            json += &stats_to_json(thread, "    ");
+        }
+        json += "\n  ]\n}\n";
+        json
+    }
+}
+
+fn stats_to_json(stats: &Stats, indent: &str) -> String {
+    let mut fields: Vec<String> = COUNTER_NAMES
+        .iter()
+        .zip(stats.counters)
+        .map(|(name, value)| format!("\"{name}\": {value}"))
+        .collect();
+    fields.extend(
+        PHASE_NAMES
+            .iter()
+            .zip(stats.phases)
+            .filter(|(_, value)| !value.is_zero())
+            .map(|(name, value)| format!("\"{name}_seconds\": {}", value.as_secs_f64())),
+    );
+    format!(
+        "{{\n{indent}  {}\n{indent}}}",
+        fields.join(&format!(",\n{indent}  "))
+    )
+}
@@ -0,0 +1,85 @@
+//! Reads meshes from STL files, as exported by CAD programs, in the ASCII and the binary format.
+
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::math3d::Vec3;
+use crate::mesh::MeshData;
+
+/// Size of the header of binary STL files, followed by the number of triangles
+const HEADER_SIZE: usize = 80;
+/// Normal and vertices as f32, followed by a u16 attribute
+const TRIANGLE_SIZE: usize = 50;
+
+/// Reads the triangles of an STL file. The facet normals in the file are ignored, they are often
+/// missing or inaccurate and follow from the vertex order anyway. Corners at the same position
+/// become shared vertices.
+pub fn parse_stl(path: &Path) -> std::io::Result<MeshData> {
+    let data = std::fs::read(path)?;
+
+    // ASCII files start with "solid", but so do some binary ones. The size of a binary file is
+    // known from its triangle count.
+    let is_binary = data.len() >= HEADER_SIZE + 4 && {
+        let count = u32::from_le_bytes(data[HEADER_SIZE..HEADER_SIZE + 4].try_into().unwrap());
+        data.len() == HEADER_SIZE + 4 + count as usize * TRIANGLE_SIZE
+    };
+    let triangles = if is_binary {
+        parse_binary(&data[HEADER_SIZE + 4..])
+    } else if data.starts_with(b"solid") {
+        parse_ascii(
+            std::str::from_utf8(&data).map_err(|_| invalid_data("Invalid ASCII STL file"))?,
+        )?
+    } else {
+        return Err(invalid_data("Not an STL file"));
+    };
+
+    MeshData::from_triangle_soup(&triangles)
+}
+
+fn parse_binary(data: &[u8]) -> Vec<[Vec3; 3]> {
+    let read_vertex = |bytes: &[u8]| {
+        let value = |i: usize| f32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap());
+        Vec3::new(value(0) as f64, value(1) as f64, value(2) as f64)
+    };
+    data.chunks_exact(TRIANGLE_SIZE)
+        .map(|triangle| {
+            [
+                read_vertex(&triangle[12..24]),
+                read_vertex(&triangle[24..36]),
+                read_vertex(&triangle[36..48]),
+            ]
+        })
+        .collect()
+}
+
+fn parse_ascii(text: &str) -> std::io::Result<Vec<[Vec3; 3]>> {
+    let mut vertices = Vec::new();
+    let mut tokens = text.split_ascii_whitespace();
+    while let Some(token) = tokens.next() {
+        // Everything but the vertices is structure, like "facet normal" and "outer loop"
+        if token != "vertex" {
+            continue;
+        }
+        let mut coordinate = || -> std::io::Result<f64> {
+            let value = tokens
+                .next()
+                .ok_or_else(|| invalid_data("Unexpected end of STL file"))?;
+            value
+                .parse()
+                .map_err(|_| invalid_data(&format!("Invalid STL coordinate '{value}'")))
+        };
+        vertices.push(Vec3::new(coordinate()?, coordinate()?, coordinate()?));
+    }
+
+    if vertices.len() % 3 != 0 {
+        return Err(invalid_data("STL facets must have three vertices"));
+    }
+    Ok(vertices
+        .chunks_exact(3)
+        .map(|v| [v[0], v[1], v[2]])
+        .collect())
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,91 @@
+//! Textures read from PNG files, mapped onto surfaces by their uv coordinates.
+
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::Path;
+
+use crate::math3d::Vec3;
+
+/// An image mapped onto surfaces by their uv coordinates. It repeats outside of the unit square.
+pub struct Texture {
+    width: usize,
+    height: usize,
+    /// Linear colors, row by row from the top
+    pixels: Vec<Vec3>,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Texture {
+        assert_eq!(pixels.len(), width * height);
+        Texture {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Reads a texture from a PNG file. Its colors are decoded with the same gamma of 2 that
+    /// rendered images are encoded with.
+    pub fn load(path: &Path) -> std::io::Result<Texture> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        // Palettes and low bit depths become 8-bit channels
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut data)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let channels = info.color_type.samples();
+        let decode = |value: u8| {
+            let value = value as f64 / 255.0;
+            value * value
+        };
+        let pixels = data[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|pixel| match pixel.len() {
+                // Gray, with or without alpha
+                1 | 2 => Vec3::new(decode(pixel[0]), decode(pixel[0]), decode(pixel[0])),
+                _ => Vec3::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2])),
+            })
+            .collect();
+        Ok(Texture::new(
+            info.width as usize,
+            info.height as usize,
+            pixels,
+        ))
+    }
+
+    /// Returns the bilinearly interpolated color at (`u`, `v`). The texture spans the unit
+    /// square, with v pointing up.
+    pub fn sample(&self, (u, v): (f64, f64)) -> Vec3 {
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |x: f64, y: f64| {
+            let x = (x as i64).rem_euclid(self.width as i64) as usize;
+            let y = (y as i64).rem_euclid(self.height as i64) as usize;
+            self.pixels[y * self.width + x]
+        };
+        let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+        let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// Returns the change of the brightness per unit of u and of v at (`u`, `v`), e.g. to use
+    /// the texture as a height map.
+    pub fn gradient(&self, (u, v): (f64, f64)) -> (f64, f64) {
+        let brightness = |u: f64, v: f64| {
+            let color = self.sample((u, v));
+            (color.x + color.y + color.z) / 3.0
+        };
+        let (du, dv) = (1.0 / self.width as f64, 1.0 / self.height as f64);
+        (
+            (brightness(u + du, v) - brightness(u - du, v)) / (2.0 * du),
+            (brightness(u, v + dv) - brightness(u, v - dv)) / (2.0 * dv),
+        )
+    }
+}
@@ -0,0 +1,113 @@
+/// A rectangular block of pixels that is rendered as one unit of work.
+#[derive(Debug, Copy, Clone)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    pub fn num_pixels(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+/// The order in which tiles are handed out to the worker threads.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Row by row, left to right
+    Scanline,
+    /// Outwards from the image center, ring by ring
+    Spiral,
+    /// Along a Hilbert curve over the smallest power-of-two square of tiles that covers the
+    /// image, skipping the cells outside of it. Consecutive tiles are mostly neighbors, except
+    /// where the curve leaves the image and re-enters it elsewhere.
+    Hilbert,
+}
+
+impl std::str::FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("Unknown tile order '{s}'")),
+        }
+    }
+}
+
+/// Splits an image of the given size into square tiles of (at most) `tile_size` pixels edge
+/// length. Tiles at the right and bottom border are cropped to the image size.
+pub fn generate_tiles(
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    order: TileOrder,
+) -> Vec<Tile> {
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+
+    let mut cells: Vec<(usize, usize)> = (0..tiles_y)
+        .flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty)))
+        .collect();
+
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let cx = (tiles_x as f64 - 1.0) / 2.0;
+            let cy = (tiles_y as f64 - 1.0) / 2.0;
+            let key = |&(tx, ty): &(usize, usize)| {
+                let dx = tx as f64 - cx;
+                let dy = ty as f64 - cy;
+                // Chebyshev distance selects the ring, the angle the position within the ring
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let n = tiles_x.max(tiles_y).next_power_of_two();
+            cells.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+
+    cells
+        .into_iter()
+        .map(|(tx, ty)| {
+            let x = tx * tile_size;
+            let y = ty * tile_size;
+            Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+// Taken from https://en.wikipedia.org/wiki/Hilbert_curve#Applications_and_mapping_algorithms
+// `n` is the edge length of the curve's square grid and needs to be a power of two.
+fn hilbert_index(n: usize, x: usize, y: usize) -> usize {
+    let (mut x, mut y) = (x, y);
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+
+        // rotate the quadrant so that the sub-curve is oriented correctly
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
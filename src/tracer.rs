@@ -0,0 +1,335 @@
+use std::f64::consts::PI;
+
+use crate::math3d::Vec3;
+use crate::media;
+use crate::ray::Ray;
+use crate::render::RenderSettings;
+use crate::scene::Scene;
+use crate::scene_objects::{HitRecord, SceneObject};
+use crate::stats::{self, Counter};
+
+/// Radiance of the constant ambient light, which approximates the light arriving indirectly
+pub(crate) const AMBIENT: f64 = 0.1;
+
+/// Offset of the origins of secondary rays from the surface, relative to the magnitude of their
+/// coordinates. Hit points are accurate to a few units in the last place of their coordinates,
+/// and curved surfaces add the error of solving for the distance, so this leaves a wide margin
+/// while staying far below the size of a pixel.
+const RELATIVE_RAY_OFFSET: f64 = 1E-7;
+
+/// Moves the point `p` on a surface with the normal `n` off the surface, to the side that `dir`
+/// points to, so that a ray from there along `dir` does not hit the surface again due to
+/// rounding errors. This works for rays into and out of the object, whichever way the normal
+/// faces. The offset grows with the magnitude of the coordinates, as their rounding errors do.
+pub fn offset_ray_origin(p: &Vec3, n: &Vec3, dir: &Vec3) -> Vec3 {
+    let magnitude = p.x.abs().max(p.y.abs()).max(p.z.abs()).max(1.0);
+    let offset = n * (magnitude * RELATIVE_RAY_OFFSET);
+    if n.dot(*dir) < 0.0 {
+        p - &offset
+    } else {
+        p + offset
+    }
+}
+
+/// Returns the surface normal `n`, flipped if necessary to face against `dir`, the direction of
+/// the ray that hit the surface. The side of the surface that the ray arrived at is shaded, also
+/// on the back of open surfaces and on the inside of closed objects.
+pub fn facing_normal(n: &Vec3, dir: &Vec3) -> Vec3 {
+    if n.dot(*dir) > 0.0 {
+        -n
+    } else {
+        *n
+    }
+}
+
+/// Parameters of the ambient occlusion approximation of indirect lighting.
+#[derive(Debug, Clone)]
+pub struct AmbientOcclusion {
+    /// Number of occlusion rays per shading point
+    pub samples: u32,
+    /// Geometry farther away than this does not occlude
+    pub max_distance: f64,
+    /// Whether ambient occlusion is computed at every hit, or only at the hit of the camera ray
+    pub all_depths: bool,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion {
+            samples: 16,
+            max_distance: 200.0,
+            all_depths: false,
+        }
+    }
+}
+
+/// Parameters of the image-based lighting, which replaces the constant ambient light by the
+/// light arriving from the environment of the scene.
+#[derive(Debug, Clone)]
+pub struct ImageBasedLighting {
+    /// Number of rays towards the environment per shading point
+    pub samples: u32,
+}
+
+impl Default for ImageBasedLighting {
+    fn default() -> Self {
+        ImageBasedLighting { samples: 16 }
+    }
+}
+
+/// Returns the `i`th of `strata` x `strata` jittered points on a grid over the unit square, or a
+/// random point if `i` is beyond the grid.
+fn stratified_sample(i: u32, strata: u32, rng: &mut dyn rand::RngCore) -> (f64, f64) {
+    use rand::Rng;
+
+    if i < strata * strata {
+        (
+            ((i % strata) as f64 + rng.gen::<f64>()) / strata as f64,
+            ((i / strata) as f64 + rng.gen::<f64>()) / strata as f64,
+        )
+    } else {
+        (rng.gen::<f64>(), rng.gen::<f64>())
+    }
+}
+
+/// Returns the fraction of the hemisphere around `normal` that is not occluded within
+/// `settings.max_distance` from `pos`, weighted by the cosine to the normal. `pos` must be
+/// offset from the surface, see `offset_ray_origin()`. The hemisphere is sampled on a
+/// jittered grid, so that the result is less noisy than with independent random samples.
+pub fn ambient_occlusion(
+    objects: &[Box<SceneObject>],
+    pos: &Vec3,
+    normal: &Vec3,
+    time: f64,
+    rng: &mut dyn rand::RngCore,
+    settings: &AmbientOcclusion,
+) -> f64 {
+    let num_samples = settings.samples.max(1);
+    // Samples beyond the largest square number that fits are not stratified
+    let strata = (num_samples as f64).sqrt() as u32;
+
+    let mut num_hits = 0;
+    for i in 0..num_samples {
+        let (u1, u2) = stratified_sample(i, strata, rng);
+        let d = normal.get_cosine_distributed_ray(u1, u2);
+        stats::count(Counter::ShadowRays, 1);
+        let ray = Ray::new(*pos, d, time).with_interval(0.0, settings.max_distance);
+        if trace_any(objects, &ray) {
+            num_hits += 1;
+        }
+    }
+
+    1.0 - (num_hits as f64) / (num_samples as f64)
+}
+
+/// Returns the light from the environment of the scene that a white diffuse surface at `pos`
+/// with `normal` reflects. The directions towards the environment are importance sampled by
+/// its brightness; directions that are blocked by objects do not contribute.
+pub fn image_based_lighting(
+    scene: &Scene,
+    pos: &Vec3,
+    normal: &Vec3,
+    time: f64,
+    rng: &mut dyn rand::RngCore,
+    settings: &ImageBasedLighting,
+) -> Vec3 {
+    let num_samples = settings.samples.max(1);
+    let strata = (num_samples as f64).sqrt() as u32;
+
+    let mut sum = Vec3::new(0.0, 0.0, 0.0);
+    for i in 0..num_samples {
+        let (u1, u2) = stratified_sample(i, strata, rng);
+        let Some((d, pdf)) = scene.environment.sample(normal, u1, u2) else {
+            continue;
+        };
+        let cos = d.dot(*normal);
+        if cos <= 0.0 || pdf <= 0.0 {
+            continue;
+        }
+        stats::count(Counter::ShadowRays, 1);
+        if !trace_any(&scene.objects, &Ray::new(*pos, d, time)) {
+            sum = sum + scene.environment.ambient_radiance(&d) * (cos / (PI * pdf));
+        }
+    }
+
+    sum * (1.0 / num_samples as f64)
+}
+
+/// Returns whether the ray hits any of the objects within its interval.
+pub fn trace_any(objects: &[Box<SceneObject>], ray: &Ray) -> bool {
+    for (i, obj) in objects.iter().enumerate() {
+        if obj.hit_any(ray) {
+            stats::count(Counter::ObjectTests, i as u64 + 1);
+            return true;
+        }
+    }
+    stats::count(Counter::ObjectTests, objects.len() as u64);
+    false
+}
+
+/// Finds the closest intersection of the ray with any of the objects within its interval, at
+/// their positions at the time of the ray.
+pub fn trace_ray<'a>(objects: &'a [Box<SceneObject>], ray: &Ray) -> Option<HitRecord<'a>> {
+    trace_ray_with_index(objects, ray).map(|(_, hit)| hit)
+}
+
+/// Like `trace_ray()`, but also returns the index of the object that was hit.
+pub fn trace_ray_with_index<'a>(
+    objects: &'a [Box<SceneObject>],
+    ray: &Ray,
+) -> Option<(usize, HitRecord<'a>)> {
+    let mut hit_obj: Option<(usize, HitRecord)> = None;
+    stats::count(Counter::ObjectTests, objects.len() as u64);
+
+    // Once an object is hit, the following ones only need to be hit closer than it
+    let mut ray = *ray;
+    for (index, obj) in objects.iter().enumerate() {
+        if let Some(hit) = obj.hit(&ray) {
+            ray.t_max = hit.distance;
+            hit_obj = Some((index, hit));
+        }
+    }
+
+    hit_obj
+}
+
+/// Computes the radiance arriving at `ray_src` from direction `ray_dir` at `time`.
+/// `recursion_depth` is the number of reflections that led to this ray, zero for camera rays.
+pub fn get_color(
+    scene: &Scene,
+    settings: &RenderSettings,
+    ray_src: &Vec3,
+    ray_dir: &Vec3,
+    time: f64,
+    rng: &mut dyn rand::RngCore,
+    recursion_depth: u32,
+) -> Vec3 {
+    if recursion_depth > 5 {
+        return Vec3::new(0.5, 0.5, 0.5);
+    }
+
+    let ray_type = if recursion_depth == 0 {
+        Counter::PrimaryRays
+    } else {
+        Counter::SecondaryRays
+    };
+    stats::count(ray_type, 1);
+
+    let ray = Ray::new(*ray_src, *ray_dir, time);
+    let hit = trace_ray(&scene.objects, &ray);
+    shade(scene, settings, &ray, hit.as_ref(), rng, recursion_depth)
+}
+
+/// Computes the radiance arriving at the origin of `ray` given `hit`, the closest hit of the
+/// ray, or `None` if it does not hit anything. Lets camera rays be traced once for both the
+/// color and the AOVs.
+pub fn shade(
+    scene: &Scene,
+    settings: &RenderSettings,
+    ray: &Ray,
+    hit: Option<&HitRecord>,
+    rng: &mut dyn rand::RngCore,
+    recursion_depth: u32,
+) -> Vec3 {
+    let (ray_src, ray_dir, time) = (ray.origin(), ray.direction(), ray.time);
+    let light_dir = &scene.light_dir;
+
+    let (radiance, distance) = if let Some(obj) = hit {
+        let p_hit = ray_src + ray_dir * obj.distance;
+        let n = facing_normal(&obj.normal, ray_dir);
+        // Origin of the rays towards the environment and for ambient occlusion
+        let p_outside = offset_ray_origin(&p_hit, &n, &n);
+
+        let diffuse = clamp(Vec3::dot(n, *light_dir), 0.0, 1.0);
+
+        let material = obj.surface_material();
+        let surface_color = obj.color.unwrap_or(material.color);
+        let light_color = Vec3::new(1.0, 0.7, 0.8);
+        let r = ray_dir.reflect_at(&n);
+        let specular = clamp(Vec3::dot(r, *light_dir), 0.0, 1.0).powf(material.specular_exponent)
+            * material.specular_strength;
+
+        let lit_color = if let Some(ibl) = &settings.image_based_lighting {
+            // The environment replaces the ambient light, it is occluded by the objects already
+            let environment = image_based_lighting(scene, &p_outside, &n, time, rng, ibl);
+            surface_color * diffuse + surface_color.mul_elementwise(&environment)
+        } else {
+            let mut brightness = diffuse + AMBIENT;
+            if let Some(ao) = &settings.ambient_occlusion {
+                if recursion_depth == 0 || ao.all_depths {
+                    brightness *= ambient_occlusion(&scene.objects, &p_outside, &n, time, rng, ao);
+                }
+            }
+            surface_color * brightness
+        };
+        let color = lit_color + light_color * specular;
+        let color = if material.reflectance > 0.0 {
+            let reflected = ray_dir.reflect_at(&n);
+            color * (1.0 - material.reflectance)
+                + get_color(
+                    scene,
+                    settings,
+                    &offset_ray_origin(&p_hit, &n, &reflected),
+                    &reflected,
+                    time,
+                    rng,
+                    recursion_depth + 1,
+                ) * material.reflectance
+        } else {
+            color
+        };
+        // Light passes straight through the transparent part of the surface
+        let color = if material.opacity < 1.0 {
+            color * material.opacity
+                + get_color(
+                    scene,
+                    settings,
+                    &offset_ray_origin(&p_hit, &n, ray_dir),
+                    ray_dir,
+                    time,
+                    rng,
+                    recursion_depth + 1,
+                ) * (1.0 - material.opacity)
+        } else {
+            color
+        };
+        (color, obj.distance)
+    } else {
+        (scene.environment.radiance(ray_dir), f64::INFINITY)
+    };
+
+    media::attenuate(scene, ray_src, ray_dir, time, distance, radiance, rng)
+}
+
+fn clamp(v: f64, min: f64, max: f64) -> f64 {
+    if v < min {
+        return min;
+    }
+
+    if v > max {
+        return max;
+    }
+
+    v
+}
+
+/// Computes the ambient occlusion at the point where `ray` hits, given `hit`, its closest hit,
+/// as a gray value. Rays that do not hit anything are white.
+pub fn get_ambient_occlusion(
+    scene: &Scene,
+    settings: &AmbientOcclusion,
+    ray: &Ray,
+    hit: Option<&HitRecord>,
+    rng: &mut dyn rand::RngCore,
+) -> Vec3 {
+    let ao = match hit {
+        Some(obj) => {
+            let p_hit = ray.at(obj.distance);
+            let n = facing_normal(&obj.normal, ray.direction());
+            let p_outside = offset_ray_origin(&p_hit, &n, &n);
+            ambient_occlusion(&scene.objects, &p_outside, &n, ray.time, rng, settings)
+        }
+        None => 1.0,
+    };
+    Vec3::new(ao, ao, ao)
+}
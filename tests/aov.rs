@@ -0,0 +1,89 @@
+//! Renders the AOVs of a small scene and checks their values on hits and misses.
+
+use rust_raytracer::aov::Aov;
+use rust_raytracer::camera::{Camera, Projection};
+use rust_raytracer::framebuffer::Framebuffer;
+use rust_raytracer::math3d::Vec3;
+use rust_raytracer::ray::Ray;
+use rust_raytracer::render::{render, RenderSettings};
+use rust_raytracer::scene::Scene;
+use rust_raytracer::scene_objects::{HitRecord, Material, Object3D, Sphere};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const AOVS: [Aov; 4] = [
+    Aov::Depth,
+    Aov::ObjectId,
+    Aov::MaterialId,
+    Aov::AmbientOcclusion,
+];
+
+/// A sphere that does not list its material in `materials()`
+struct Unlisted(Sphere);
+
+impl Object3D for Unlisted {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord<'_>> {
+        self.0.hit(ray)
+    }
+
+    fn get_material(&self) -> &Material {
+        self.0.get_material()
+    }
+
+    fn materials(&self) -> Vec<&Material> {
+        Vec::new()
+    }
+}
+
+/// Two spheres left and right of the image center, at pixel (16, 24) and (48, 24)
+fn render_aovs() -> Framebuffer {
+    let mut scene = Scene::new();
+    scene.add(Sphere::new(
+        Vec3::new(-100.0, 0.0, 200.0),
+        40.0,
+        Material::new_diffuse(Vec3::new(0.8, 0.2, 0.2)),
+    ));
+    scene.add(Unlisted(Sphere::new(
+        Vec3::new(100.0, 0.0, 200.0),
+        40.0,
+        Material::new_diffuse(Vec3::new(0.2, 0.2, 0.8)),
+    )));
+    let camera = Camera::new(Projection::Perspective, WIDTH, HEIGHT);
+    let settings = RenderSettings {
+        passes: 1,
+        aovs: AOVS.to_vec(),
+        ..RenderSettings::default()
+    };
+    render(&scene, &camera, &settings)
+}
+
+/// The values of all `AOVS` at a pixel
+fn pixel(framebuffer: &Framebuffer, x: usize, y: usize) -> [f64; 4] {
+    AOVS.map(|aov| framebuffer.aov(aov).unwrap()[y * WIDTH + x].x)
+}
+
+#[test]
+fn misses_are_zero() {
+    let framebuffer = render_aovs();
+    // Only the ambient occlusion is one where nothing is hit
+    assert_eq!(pixel(&framebuffer, 0, 0), [0.0, 0.0, 0.0, 1.0]);
+    assert_eq!(pixel(&framebuffer, 32, 24), [0.0, 0.0, 0.0, 1.0]);
+}
+
+#[test]
+fn ids_start_at_one() {
+    let framebuffer = render_aovs();
+    let [depth, object_id, material_id, _] = pixel(&framebuffer, 16, 24);
+    // The distance to the center minus the radius
+    let expected = (100.0f64 * 100.0 + 200.0 * 200.0).sqrt() - 40.0;
+    assert!((depth - expected).abs() < 5.0, "depth {depth}");
+    assert_eq!((object_id, material_id), (1.0, 1.0));
+}
+
+#[test]
+fn unlisted_material_has_id_zero() {
+    let framebuffer = render_aovs();
+    let [depth, object_id, material_id, _] = pixel(&framebuffer, 48, 24);
+    assert!(depth > 0.0);
+    assert_eq!((object_id, material_id), (2.0, 0.0));
+}
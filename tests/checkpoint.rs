@@ -0,0 +1,129 @@
+//! Interrupts renders, saves them to checkpoints and resumes them from there.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use rust_raytracer::aov::Aov;
+use rust_raytracer::camera::{Camera, Projection};
+use rust_raytracer::checkpoint;
+use rust_raytracer::framebuffer::Framebuffer;
+use rust_raytracer::math3d::Vec3;
+use rust_raytracer::render::{render, render_from, RenderObserver, RenderSettings};
+use rust_raytracer::scene::Scene;
+use rust_raytracer::scene_objects::{Material, Plane, Sphere};
+use rust_raytracer::tiles::TileOrder;
+use rust_raytracer::tracer::AmbientOcclusion;
+
+fn scene() -> Scene {
+    let mut scene = Scene::new();
+    scene.add(Sphere::new(
+        Vec3::new(0.0, 0.0, 150.0),
+        50.0,
+        Material::new_diffuse(Vec3::new(0.8, 0.2, 0.2)),
+    ));
+    scene.add(Plane::new(
+        Vec3::new(0.0, 50.0, 0.0),
+        Vec3::new(0.0, -1.0, 0.0),
+        Material::new_diffuse(Vec3::new(0.1, 0.5, 0.1)),
+    ));
+    scene
+}
+
+fn settings() -> RenderSettings {
+    RenderSettings {
+        passes: 3,
+        seed: 99,
+        threads: Some(3),
+        tile_size: 8,
+        ambient_occlusion: Some(AmbientOcclusion::default()),
+        aovs: vec![Aov::Depth, Aov::AmbientOcclusion],
+        ..RenderSettings::default()
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rust_raytracer_{name}_{}", std::process::id()))
+}
+
+/// The complete state of a framebuffer, as written to checkpoints
+fn bytes(framebuffer: &Framebuffer) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    framebuffer.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+/// Stops the render once a number of tiles are finished.
+struct StopAfter {
+    tiles: usize,
+    done: AtomicUsize,
+}
+
+impl RenderObserver for StopAfter {
+    fn tile_done(&self, _pass: usize, _rays: u64, _framebuffer: &Mutex<Framebuffer>) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn should_stop(&self) -> bool {
+        self.done.load(Ordering::Relaxed) >= self.tiles
+    }
+}
+
+#[test]
+fn resumed_render_is_identical() {
+    let scene = scene();
+    let camera = Camera::new(Projection::Perspective, 64, 48);
+    let settings = settings();
+    let uninterrupted = render(&scene, &camera, &settings);
+
+    // 48 tiles per pass, stopped in the middle of the second pass
+    let observer = StopAfter {
+        tiles: 70,
+        done: AtomicUsize::new(0),
+    };
+    let framebuffer = Framebuffer::with_aovs(64, 48, &settings.aovs);
+    let outcome = render_from(&scene, &camera, &settings, framebuffer, 0, &observer);
+    let pass = outcome.stopped_in_pass.expect("The render was stopped");
+
+    let path = temp_path("resume.ckpt");
+    checkpoint::save(
+        &path,
+        settings.seed,
+        pass,
+        settings.tile_size,
+        settings.tile_order,
+        "options",
+        &outcome.framebuffer,
+    )
+    .unwrap();
+    let loaded = checkpoint::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.options, "options");
+
+    let resumed = render_from(
+        &scene,
+        &camera,
+        &settings,
+        loaded.framebuffer,
+        loaded.pass,
+        &(),
+    );
+    assert_eq!(resumed.stopped_in_pass, None);
+    assert!(bytes(&resumed.framebuffer) == bytes(&uninterrupted));
+}
+
+#[test]
+fn zero_tile_size_is_rejected() {
+    let path = temp_path("tile_size.ckpt");
+    let framebuffer = Framebuffer::new(4, 4);
+    checkpoint::save(&path, 0, 0, 1, TileOrder::Scanline, "", &framebuffer).unwrap();
+    let mut data = std::fs::read(&path).unwrap();
+    // The tile size follows the magic, version, size, seed and pass
+    data[32..36].copy_from_slice(&0u32.to_le_bytes());
+    std::fs::write(&path, data).unwrap();
+    let result = checkpoint::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    let error = result.err().expect("A tile size of zero was accepted");
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,83 @@
+//! Denoises synthetic framebuffers and checks that edges in the guide buffers are kept.
+
+use rust_raytracer::denoise::{DenoiseInput, DenoiseSettings, GUIDE_AOVS};
+use rust_raytracer::framebuffer::Framebuffer;
+use rust_raytracer::math3d::Vec3;
+use rust_raytracer::tiles::Tile;
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 16;
+
+/// A framebuffer with one sample per pixel. `pixel` returns the color and the normal of a
+/// pixel, the albedo is white and the depth is constant.
+fn framebuffer(pixel: impl Fn(usize, usize) -> (Vec3, Vec3)) -> Framebuffer {
+    let mut framebuffer = Framebuffer::with_aovs(WIDTH, HEIGHT, &GUIDE_AOVS);
+    let tile = Tile {
+        x: 0,
+        y: 0,
+        width: WIDTH,
+        height: HEIGHT,
+    };
+    let mut values = Vec::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let (color, normal) = pixel(x, y);
+            // In the order of `GUIDE_AOVS`, after the color
+            values.extend([
+                color,
+                normal,
+                Vec3::new(1.0, 1.0, 1.0),
+                Vec3::new(10.0, 10.0, 10.0),
+            ]);
+        }
+    }
+    framebuffer.add_tile(&tile, &values);
+    framebuffer
+}
+
+fn denoise(framebuffer: &Framebuffer) -> Vec<Vec3> {
+    let settings = DenoiseSettings {
+        threads: Some(2),
+        ..DenoiseSettings::default()
+    };
+    DenoiseInput::from_framebuffer(framebuffer)
+        .unwrap()
+        .denoise(&settings)
+}
+
+fn gray(v: f64) -> Vec3 {
+    Vec3::new(v, v, v)
+}
+
+#[test]
+fn flat_region_stays_flat() {
+    let normal = Vec3::new(0.0, 0.0, -1.0);
+    let denoised = denoise(&framebuffer(|_, _| (gray(0.5), normal)));
+    for color in denoised {
+        assert!((color - gray(0.5)).len() < 1E-9, "{color:?}");
+    }
+}
+
+#[test]
+fn keeps_edges() {
+    // Two faces that meet in the middle of the image, each with a checkerboard of noise
+    let (left, right) = (Vec3::new(0.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 0.0));
+    let framebuffer = framebuffer(|x, y| {
+        let noise = if (x + y) % 2 == 0 { 0.05 } else { -0.05 };
+        if x < WIDTH / 2 {
+            (gray(0.2 + noise), left)
+        } else {
+            (gray(0.8 + noise), right)
+        }
+    });
+    let denoised = denoise(&framebuffer);
+    for y in 0..HEIGHT {
+        // The pixels on both sides of the edge keep the brightness of their face
+        let (a, b) = (
+            denoised[y * WIDTH + WIDTH / 2 - 1],
+            denoised[y * WIDTH + WIDTH / 2],
+        );
+        assert!((a - gray(0.2)).len() < 0.05, "{a:?}");
+        assert!((b - gray(0.8)).len() < 0.05, "{b:?}");
+    }
+}
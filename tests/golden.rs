@@ -0,0 +1,564 @@
+//! Golden-image regression tests: small canonical scenes are rendered with a fixed seed and
+//! compared against the reference images in `tests/golden/`. On a mismatch, the rendered image
+//! and a difference image are written to `target/golden-diff/`.
+//!
+//! Run with `UPDATE_GOLDEN=1 cargo test` to (re-)generate the reference images after an
+//! intentional change of the renderer's output.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rust_raytracer::animation::Keyframes;
+use rust_raytracer::camera::{Camera, Projection};
+use rust_raytracer::csg::Csg;
+use rust_raytracer::framebuffer;
+use rust_raytracer::math3d::{Rotation, Transform, Vec3};
+use rust_raytracer::media::{Density, Fog, Volume};
+use rust_raytracer::mesh::TriangleMesh;
+use rust_raytracer::primitives::{
+    AxisAlignedBox, Cone, Cylinder, Disk, OrientedBox, Rectangle, Torus,
+};
+use rust_raytracer::render::{render, RenderSettings};
+use rust_raytracer::scene::Scene;
+use rust_raytracer::scene_objects::{Instance, Material, Plane, SceneObject, Sphere};
+use rust_raytracer::sdf::{self, Capsule, Cuboid, Mandelbulb, Sdf, SdfObject};
+use rust_raytracer::tiles::TileOrder;
+use rust_raytracer::tracer::AmbientOcclusion;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const SEED: u64 = 1234;
+/// Minimum peak signal-to-noise ratio in dB for an image to match its reference
+const MIN_PSNR: f64 = 50.0;
+
+/// Horizontal plane at height `y`. The y axis points downwards.
+fn ground(y: f64) -> Box<SceneObject> {
+    Box::new(Plane::new(
+        Vec3::new(0.0, y, 0.0),
+        Vec3::new(0.0, -1.0, 0.0),
+        Material::new_diffuse(Vec3::new(0.1, 0.5, 0.1)),
+    ))
+}
+
+fn render_scene(objects: Vec<Box<SceneObject>>, projection: Projection, passes: usize) -> Vec<u8> {
+    let camera = Camera::new(projection, WIDTH, HEIGHT);
+    render_with_camera(objects, &camera, passes)
+}
+
+fn render_with_camera(objects: Vec<Box<SceneObject>>, camera: &Camera, passes: usize) -> Vec<u8> {
+    let scene = Scene {
+        objects,
+        ..Scene::new()
+    };
+    let settings = RenderSettings {
+        passes,
+        seed: SEED,
+        ..RenderSettings::default()
+    };
+    render(&scene, camera, &settings).to_rgba8()
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn read_png(path: &Path) -> (usize, usize, Vec<u8>) {
+    let decoder = png::Decoder::new(std::fs::File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba);
+    data.truncate(info.buffer_size());
+    (info.width as usize, info.height as usize, data)
+}
+
+/// Peak signal-to-noise ratio of the RGB channels of two RGBA images, in dB
+fn psnr(a: &[u8], b: &[u8]) -> f64 {
+    let (sum, count) = a
+        .chunks(4)
+        .zip(b.chunks(4))
+        .flat_map(|(pa, pb)| pa[..3].iter().zip(&pb[..3]))
+        .fold((0.0, 0), |(sum, count), (&ca, &cb)| {
+            let d = ca as f64 - cb as f64;
+            (sum + d * d, count + 1)
+        });
+    let mse = sum / count as f64;
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+fn assert_matches_golden(name: &str, rgba: &[u8]) {
+    let golden_path = golden_dir().join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        framebuffer::save_png(&golden_path, WIDTH, HEIGHT, rgba).unwrap();
+        return;
+    }
+
+    let (width, height, golden) = read_png(&golden_path);
+    assert_eq!((width, height), (WIDTH, HEIGHT), "Size of {name}");
+    let psnr = psnr(rgba, &golden);
+    if psnr >= MIN_PSNR {
+        return;
+    }
+
+    // Amplify the differences so that small deviations are visible
+    let diff: Vec<u8> = rgba
+        .chunks(4)
+        .zip(golden.chunks(4))
+        .flat_map(|(a, b)| {
+            let d = |i: usize| (a[i].abs_diff(b[i]) as u32 * 8).min(255) as u8;
+            [d(0), d(1), d(2), 255]
+        })
+        .collect();
+    let diff_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden-diff");
+    std::fs::create_dir_all(&diff_dir).unwrap();
+    let actual_path = diff_dir.join(format!("{name}-actual.png"));
+    let diff_path = diff_dir.join(format!("{name}-diff.png"));
+    framebuffer::save_png(&actual_path, WIDTH, HEIGHT, rgba).unwrap();
+    framebuffer::save_png(&diff_path, WIDTH, HEIGHT, &diff).unwrap();
+
+    panic!(
+        "{name} differs from its reference image (PSNR {psnr:.1} dB < {MIN_PSNR} dB), see {} and {}",
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+/// Three spheres, one of them shiny
+fn spheres_objects() -> Vec<Box<SceneObject>> {
+    vec![
+        Box::new(Sphere::new(
+            Vec3::new(-60.0, 0.0, 150.0),
+            50.0,
+            Material::new_diffuse(Vec3::new(0.8, 0.2, 0.2)),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(60.0, 0.0, 150.0),
+            50.0,
+            Material::new(Vec3::new(0.2, 0.2, 0.8), 0.0, 1.0, 20.0),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(0.0, -40.0, 100.0),
+            20.0,
+            Material::new_diffuse(Vec3::new(0.9, 0.9, 0.2)),
+        )),
+    ]
+}
+
+#[test]
+fn spheres() {
+    assert_matches_golden(
+        "spheres",
+        &render_scene(spheres_objects(), Projection::Perspective, 4),
+    );
+}
+
+/// Renders are bit-identical regardless of the number of threads and the tiling.
+#[test]
+fn threads_and_tiles_do_not_change_the_result() {
+    let mut objects = spheres_objects();
+    objects.push(ground(50.0));
+    let scene = Scene {
+        objects,
+        ..Scene::new()
+    };
+    let camera = Camera::new(Projection::Perspective, WIDTH, HEIGHT);
+    let render_with = |threads: usize, tile_size: usize, tile_order: TileOrder| {
+        let settings = RenderSettings {
+            passes: 4,
+            seed: SEED,
+            threads: Some(threads),
+            tile_size,
+            tile_order,
+            ambient_occlusion: Some(AmbientOcclusion::default()),
+            ..RenderSettings::default()
+        };
+        let mut bytes = Vec::new();
+        render(&scene, &camera, &settings)
+            .write_to(&mut bytes)
+            .unwrap();
+        bytes
+    };
+    let single = render_with(1, 8, TileOrder::Scanline);
+    let parallel = render_with(4, 32, TileOrder::Hilbert);
+    assert!(single == parallel, "The renders differ");
+}
+
+#[test]
+fn plane() {
+    let objects = vec![
+        ground(100.0),
+        Box::new(Plane::new(
+            Vec3::new(0.0, 0.0, 1000.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Material::new_diffuse(Vec3::new(0.6, 0.6, 0.6)),
+        )),
+    ];
+    assert_matches_golden("plane", &render_scene(objects, Projection::Perspective, 1));
+}
+
+#[test]
+fn reflections() {
+    let objects = vec![
+        ground(100.0),
+        Box::new(Sphere::new(
+            Vec3::new(-50.0, 50.0, 150.0),
+            50.0,
+            Material::new(Vec3::new(0.9, 0.9, 0.9), 0.8, 1.0, 50.0),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(50.0, 50.0, 150.0),
+            50.0,
+            Material::new(Vec3::new(0.9, 0.5, 0.2), 0.3, 1.0, 50.0),
+        )),
+    ];
+    assert_matches_golden(
+        "reflections",
+        &render_scene(objects, Projection::Perspective, 4),
+    );
+}
+
+#[test]
+fn primitives() {
+    let up = Vec3::new(0.0, -1.0, 0.0);
+    let objects: Vec<Box<SceneObject>> = vec![
+        ground(60.0),
+        Box::new(AxisAlignedBox::new(
+            Vec3::new(-150.0, 60.0, 280.0),
+            Vec3::new(-90.0, 0.0, 340.0),
+            Material::new_diffuse(Vec3::new(0.8, 0.2, 0.2)),
+        )),
+        Box::new(OrientedBox::new(
+            Vec3::new(-40.0, 30.0, 300.0),
+            Vec3::new(50.0, 50.0, 50.0),
+            Rotation::from_axis_angle(Vec3::new(1.0, 1.0, 0.0).normalized(), 0.6),
+            Material::new(Vec3::new(0.9, 0.6, 0.2), 0.0, 0.5, 20.0),
+        )),
+        Box::new(Cylinder::new(
+            Vec3::new(40.0, 60.0, 300.0),
+            up,
+            25.0,
+            70.0,
+            Material::new(Vec3::new(0.2, 0.4, 0.9), 0.0, 1.0, 30.0),
+        )),
+        Box::new(Cone::new(
+            Vec3::new(120.0, 60.0, 300.0),
+            up,
+            30.0,
+            80.0,
+            Material::new_diffuse(Vec3::new(0.9, 0.9, 0.2)),
+        )),
+        Box::new(Torus::new(
+            Vec3::new(-80.0, 0.0, 180.0),
+            Vec3::new(0.0, -1.0, -1.0),
+            25.0,
+            8.0,
+            Material::new(Vec3::new(0.7, 0.2, 0.8), 0.0, 1.0, 30.0),
+        )),
+        Box::new(Disk::new(
+            Vec3::new(0.0, 20.0, 200.0),
+            Vec3::new(0.0, -1.0, -2.0),
+            25.0,
+            Material::new_diffuse(Vec3::new(0.2, 0.8, 0.8)),
+        )),
+        Box::new(Rectangle::new(
+            Vec3::new(50.0, 50.0, 170.0),
+            Vec3::new(50.0, 0.0, 0.0),
+            Vec3::new(0.0, -40.0, 20.0),
+            Material::new(Vec3::new(0.9, 0.9, 0.9), 0.6, 1.0, 50.0),
+        )),
+    ];
+    assert_matches_golden(
+        "primitives",
+        &render_scene(objects, Projection::Perspective, 4),
+    );
+}
+
+#[test]
+fn csg() {
+    let red = || Material::new(Vec3::new(0.8, 0.2, 0.2), 0.0, 1.0, 30.0);
+    let blue = || Material::new_diffuse(Vec3::new(0.2, 0.4, 0.9));
+    let yellow = || Material::new_diffuse(Vec3::new(0.9, 0.9, 0.2));
+    // A lens, where two spheres overlap
+    let lens = Csg::intersection(
+        Sphere::new(Vec3::new(-120.0, 0.0, 300.0), 60.0, blue()),
+        Sphere::new(Vec3::new(-80.0, 0.0, 300.0), 60.0, blue()),
+    );
+    // A rounded cube, drilled through by a cylinder
+    let drilled = Csg::difference(
+        Csg::intersection(
+            AxisAlignedBox::new(
+                Vec3::new(-30.0, -30.0, 270.0),
+                Vec3::new(30.0, 30.0, 330.0),
+                red(),
+            ),
+            Sphere::new(Vec3::new(0.0, 0.0, 300.0), 40.0, red()),
+        ),
+        Cylinder::new(
+            Vec3::new(0.0, 0.0, 250.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            15.0,
+            100.0,
+            yellow(),
+        ),
+    );
+    // A hemisphere, cut by a plane, with a bowl carved into it
+    let bowl = Csg::difference(
+        Csg::difference(
+            Sphere::new(Vec3::new(110.0, 10.0, 250.0), 50.0, yellow()),
+            Plane::new(
+                Vec3::new(0.0, 10.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                yellow(),
+            ),
+        ),
+        Sphere::new(Vec3::new(110.0, -15.0, 250.0), 45.0, blue()),
+    );
+    let objects: Vec<Box<SceneObject>> = vec![
+        ground(60.0),
+        Box::new(lens),
+        Box::new(drilled),
+        Box::new(bowl),
+    ];
+    assert_matches_golden("csg", &render_scene(objects, Projection::Perspective, 4));
+}
+
+#[test]
+fn sdf() {
+    // Two spheres melting into each other, on top of a rounded box
+    let blob = sdf::Sphere {
+        center: Vec3::new(-15.0, -10.0, 0.0),
+        radius: 20.0,
+    }
+    .smooth_union(
+        sdf::Sphere {
+            center: Vec3::new(15.0, -10.0, 0.0),
+            radius: 15.0,
+        },
+        10.0,
+    )
+    .smooth_union(
+        Cuboid {
+            half_size: Vec3::new(30.0, 10.0, 20.0),
+        }
+        .rounded(5.0)
+        .translated(Vec3::new(0.0, 25.0, 0.0)),
+        10.0,
+    )
+    .translated(Vec3::new(-110.0, 20.0, 300.0));
+    let ring = sdf::Torus {
+        major_radius: 25.0,
+        minor_radius: 6.0,
+    }
+    .union(Capsule {
+        a: Vec3::new(-25.0, 0.0, 0.0),
+        b: Vec3::new(25.0, 0.0, 0.0),
+        radius: 4.0,
+    })
+    .rotated(Rotation::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), 1.2))
+    .translated(Vec3::new(0.0, 20.0, 250.0));
+    let bulb = Mandelbulb::new()
+        .scaled(45.0)
+        .translated(Vec3::new(110.0, 0.0, 300.0));
+    let objects: Vec<Box<SceneObject>> = vec![
+        ground(60.0),
+        Box::new(SdfObject::new(
+            blob,
+            Material::new(Vec3::new(0.8, 0.2, 0.2), 0.0, 1.0, 30.0),
+        )),
+        Box::new(SdfObject::new(
+            ring,
+            Material::new_diffuse(Vec3::new(0.9, 0.9, 0.2)),
+        )),
+        Box::new(
+            SdfObject::new(bulb, Material::new_diffuse(Vec3::new(0.2, 0.4, 0.9)))
+                .with_max_steps(512)
+                .with_epsilon(5E-2),
+        ),
+    ];
+    assert_matches_golden("sdf", &render_scene(objects, Projection::Perspective, 4));
+}
+
+#[test]
+fn media() {
+    let objects: Vec<Box<SceneObject>> = vec![
+        ground(60.0),
+        Box::new(Sphere::new(
+            Vec3::new(0.0, 20.0, 400.0),
+            40.0,
+            Material::new_diffuse(Vec3::new(0.8, 0.2, 0.2)),
+        )),
+    ];
+    let no_material = || Material::new_diffuse(Vec3::new(0.0, 0.0, 0.0));
+    // A glowing ball of smoke and a cloud whose density fades out in layers
+    let smoke = Volume::new(
+        Sphere::new(Vec3::new(-90.0, 10.0, 250.0), 40.0, no_material()),
+        Density::Homogeneous(0.03),
+        Vec3::new(0.9, 0.7, 0.3),
+    )
+    .with_anisotropy(0.5);
+    let cloud = Volume::new(
+        AxisAlignedBox::new(
+            Vec3::new(40.0, -50.0, 160.0),
+            Vec3::new(100.0, 10.0, 220.0),
+            no_material(),
+        ),
+        Density::Heterogeneous(Box::new(|p: Vec3| {
+            0.1 * (0.5 + 0.5 * (p.x * 0.1).sin() * (p.y * 0.1).cos())
+        })),
+        Vec3::new(0.9, 0.9, 0.9),
+    )
+    .with_steps(64);
+    let scene = Scene {
+        objects,
+        // Backlit, so that the media scatter the light forward towards the camera
+        light_dir: Vec3::new(0.3, -1.0, 1.0).normalized(),
+        fog: Some(Fog::new(0.001)),
+        volumes: vec![smoke, cloud],
+        ..Scene::new()
+    };
+    let settings = RenderSettings {
+        passes: 4,
+        seed: SEED,
+        ..RenderSettings::default()
+    };
+    let camera = Camera::new(Projection::Perspective, WIDTH, HEIGHT);
+    assert_matches_golden("media", &render(&scene, &camera, &settings).to_rgba8());
+}
+
+#[test]
+fn mesh_formats() {
+    // The meshes are around the origin with a radius of about one
+    let place = |file: &str, color: Vec3, x: f64, y: f64| -> Box<SceneObject> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(file);
+        let mesh = TriangleMesh::from_file(&path, Material::new(color, 0.0, 0.5, 20.0)).unwrap();
+        let transform = Transform {
+            translation: Vec3::new(x, y, 250.0),
+            rotation: Rotation::from_axis_angle(Vec3::new(1.0, 1.0, 0.0), 0.5),
+            scale: 30.0,
+        };
+        Box::new(Instance::new(
+            Arc::new(mesh),
+            Keyframes::constant(transform),
+        ))
+    };
+    let white = Vec3::new(0.9, 0.9, 0.9);
+    let objects: Vec<Box<SceneObject>> = vec![
+        ground(60.0),
+        // Vertex colors and normals
+        place("icosphere.ply", white, -100.0, -20.0),
+        // Smooth
+        place("icosphere_le.ply", Vec3::new(0.2, 0.4, 0.9), 0.0, -20.0),
+        // Flat
+        place("icosphere_be.ply", Vec3::new(0.2, 0.4, 0.9), 100.0, -20.0),
+        place("cube.stl", Vec3::new(0.9, 0.6, 0.2), -50.0, 30.0),
+        place("octahedron.stl", Vec3::new(0.9, 0.9, 0.2), 50.0, 30.0),
+    ];
+    assert_matches_golden(
+        "mesh_formats",
+        &render_scene(objects, Projection::Perspective, 1),
+    );
+}
+
+#[test]
+fn mtl_materials() {
+    // The faces of the cube have textured, glossy, mirroring, transparent and bumpy materials
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/materials.obj");
+    let cube = Arc::new(
+        TriangleMesh::from_file(&path, Material::new_diffuse(Vec3::new(0.5, 0.5, 0.5))).unwrap(),
+    );
+    let place = |axis: Vec3, angle: f64, x: f64| -> Box<SceneObject> {
+        let transform = Transform {
+            translation: Vec3::new(x, 0.0, 250.0),
+            rotation: Rotation::from_axis_angle(axis, angle),
+            scale: 35.0,
+        };
+        Box::new(Instance::new(cube.clone(), Keyframes::constant(transform)))
+    };
+    let objects: Vec<Box<SceneObject>> = vec![
+        ground(60.0),
+        place(Vec3::new(1.0, 1.0, 0.0), 0.6, -55.0),
+        place(Vec3::new(-1.0, 1.0, 0.0), 2.5, 55.0),
+    ];
+    assert_matches_golden(
+        "mtl_materials",
+        &render_scene(objects, Projection::Perspective, 1),
+    );
+}
+
+#[test]
+fn bunny() {
+    assert_matches_golden("bunny", &render_bunny(false));
+}
+
+/// Positions in single precision render like those in double precision.
+#[test]
+fn bunny_single_precision() {
+    let psnr = psnr(&render_bunny(true), &render_bunny(false));
+    assert!(psnr >= MIN_PSNR, "PSNR {psnr:.1} dB < {MIN_PSNR} dB");
+}
+
+fn render_bunny(single_precision: bool) -> Vec<u8> {
+    let mut bunny = TriangleMesh::from_obj_file(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/data/bunny.obj"),
+        Material::new(Vec3::new(0.8, 0.2, 0.2), 0.0, 0.3, 32.0),
+    )
+    .unwrap();
+    if single_precision {
+        bunny = bunny.with_single_precision();
+    }
+    let objects: Vec<Box<SceneObject>> = vec![ground(200.0), Box::new(bunny)];
+    render_scene(objects, Projection::Perspective, 1)
+}
+
+#[test]
+fn stereo_panorama() {
+    let objects = vec![
+        ground(100.0),
+        Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, 200.0),
+            50.0,
+            Material::new(Vec3::new(0.8, 0.8, 0.8), 0.2, 0.5, 20.0),
+        )),
+    ];
+    assert_matches_golden(
+        "stereo_panorama",
+        &render_scene(objects, Projection::Stereo360, 1),
+    );
+}
+
+#[test]
+fn motion_blur() {
+    // Swings around the y axis through its origin while the shutter is open
+    let swing = |angle: f64| Transform {
+        translation: Vec3::new(0.0, 0.0, 200.0),
+        rotation: Rotation::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), angle),
+        scale: 1.0,
+    };
+    let ball: Arc<SceneObject> = Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.0, -60.0),
+        15.0,
+        Material::new_diffuse(Vec3::new(0.9, 0.9, 0.2)),
+    ));
+    let objects: Vec<Box<SceneObject>> = vec![
+        ground(50.0),
+        Box::new(Sphere::moving(
+            Keyframes::linear(Vec3::new(20.0, -20.0, 200.0), Vec3::new(60.0, 0.0, 200.0)),
+            20.0,
+            Material::new_diffuse(Vec3::new(0.8, 0.2, 0.2)),
+        )),
+        Box::new(Instance::new(
+            ball,
+            Keyframes::linear(swing(0.0), swing(std::f64::consts::FRAC_PI_2)),
+        )),
+    ];
+    let mut camera = Camera::new(Projection::Perspective, WIDTH, HEIGHT);
+    camera.shutter = (0.0, 1.0);
+    assert_matches_golden("motion_blur", &render_with_camera(objects, &camera, 16));
+}
@@ -0,0 +1,62 @@
+//! Loads the test meshes from their files and checks that the different encodings of the same
+//! mesh yield the same data.
+
+use std::path::{Path, PathBuf};
+
+use rust_raytracer::mesh::MeshData;
+use rust_raytracer::ply::parse_ply;
+use rust_raytracer::stl::parse_stl;
+
+fn path(file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(file)
+}
+
+/// The positions at the precision of the files, which store them as floats. The ASCII file's
+/// decimal values are not rounded to floats when they are read.
+fn coordinates(mesh: &MeshData) -> Vec<[f32; 3]> {
+    mesh.positions
+        .iter()
+        .map(|p| [p.x as f32, p.y as f32, p.z as f32])
+        .collect()
+}
+
+#[test]
+fn ply_encodings_match() {
+    let ascii = parse_ply(&path("icosphere.ply")).unwrap();
+    assert_eq!(ascii.positions.len(), 42);
+    assert_eq!(ascii.triangles.len(), 80);
+    for file in ["icosphere_le.ply", "icosphere_be.ply"] {
+        let binary = parse_ply(&path(file)).unwrap();
+        assert_eq!(coordinates(&binary), coordinates(&ascii), "{file}");
+        assert_eq!(binary.triangles, ascii.triangles, "{file}");
+    }
+}
+
+#[test]
+fn ply_rejects_invalid_indices() {
+    let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+                  property float z\nelement face 1\nproperty list uchar int vertex_indices\n\
+                  end_header\n0 0 0\n1 0 0\n0 1 0\n";
+    let dir = std::env::temp_dir();
+    for (name, face) in [("negative", "3 0 1 -1"), ("fraction", "3 0 1 1.5")] {
+        let file = dir.join(format!("rust_raytracer_{name}_index.ply"));
+        std::fs::write(&file, format!("{header}{face}\n")).unwrap();
+        let result = parse_ply(&file);
+        std::fs::remove_file(&file).unwrap();
+        let error = result
+            .err()
+            .unwrap_or_else(|| panic!("{name} index was accepted"));
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{name}");
+    }
+}
+
+#[test]
+fn stl_triangle_counts() {
+    assert_eq!(parse_stl(&path("cube.stl")).unwrap().triangles.len(), 12);
+    assert_eq!(
+        parse_stl(&path("octahedron.stl")).unwrap().triangles.len(),
+        8
+    );
+}
@@ -0,0 +1,148 @@
+//! Checks that the SIMD intersection kernels return exactly the same results as the scalar code.
+//! On CPUs without SIMD support, both are the scalar code. Also checks that the traversal of
+//! the bounding volume hierarchy of meshes finds the closest hits.
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use rust_raytracer::math3d::Vec3;
+use rust_raytracer::mesh::TriangleMesh;
+use rust_raytracer::ray::Ray;
+use rust_raytracer::scene_objects::{Material, Object3D};
+use rust_raytracer::simd::{Boxes4, Kernels, TriangleHit, Triangles4};
+use rust_raytracer::stats::{self, Counter};
+
+const ITERATIONS: usize = 5000;
+
+/// A random coordinate, often a round number so that rays and faces line up
+fn coordinate(rng: &mut SmallRng) -> f64 {
+    match rng.gen_range(0..4) {
+        0 => 0.0,
+        1 => rng.gen_range(-2..=2) as f64,
+        _ => rng.gen_range(-2.0..2.0),
+    }
+}
+
+fn point(rng: &mut SmallRng) -> Vec3 {
+    Vec3::new(coordinate(rng), coordinate(rng), coordinate(rng))
+}
+
+fn ray(rng: &mut SmallRng) -> Ray {
+    let mut direction = point(rng);
+    if direction.squared_length() == 0.0 {
+        direction = Vec3::new(0.0, 0.0, 1.0);
+    }
+    let t_min = if rng.gen() {
+        0.0
+    } else {
+        rng.gen_range(-1.0..1.0)
+    };
+    let t_max = if rng.gen() {
+        f64::INFINITY
+    } else {
+        rng.gen_range(0.0..3.0)
+    };
+    Ray::new(point(rng) * 2.0, direction, 0.0).with_interval(t_min, t_max)
+}
+
+fn bits(hit: Option<TriangleHit>) -> Option<[u64; 4]> {
+    hit.map(|(t, u, v, error)| [t, u, v, error].map(f64::to_bits))
+}
+
+#[test]
+fn boxes_match_scalar() {
+    let mut rng = SmallRng::seed_from_u64(1);
+    for _ in 0..ITERATIONS {
+        let count = rng.gen_range(0..=4);
+        let boxes: Vec<(Vec3, Vec3)> = (0..count)
+            .map(|_| {
+                let (a, b) = (point(&mut rng), point(&mut rng));
+                (
+                    Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+                    Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+                )
+            })
+            .collect();
+        let boxes = Boxes4::new(&boxes);
+        let ray = ray(&mut rng);
+        assert_eq!(
+            Kernels::detect().hit_boxes(&ray, &boxes),
+            Kernels::scalar().hit_boxes(&ray, &boxes),
+            "{ray:?} {boxes:?}"
+        );
+    }
+}
+
+#[test]
+fn triangles_match_scalar() {
+    let mut rng = SmallRng::seed_from_u64(2);
+    for _ in 0..ITERATIONS {
+        // Neighboring triangles, so that rays hit their shared edges and vertices
+        let corners: Vec<Vec3> = (0..5).map(|_| point(&mut rng)).collect();
+        let triangles = std::array::from_fn(|i| [corners[i], corners[i + 1], corners[(i + 2) % 5]]);
+        let triangles = Triangles4::new(&triangles);
+        let ray = ray(&mut rng);
+        let simd = Kernels::detect().intersect_triangles(&ray, &triangles);
+        let scalar = Kernels::scalar().intersect_triangles(&ray, &triangles);
+        assert_eq!(simd.map(bits), scalar.map(bits), "{ray:?} {triangles:?}");
+    }
+}
+
+fn load_bunny() -> TriangleMesh {
+    TriangleMesh::from_obj_file(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/data/bunny.obj"),
+        Material::new_diffuse(Vec3::new(1.0, 1.0, 1.0)),
+    )
+    .unwrap()
+}
+
+/// Rays from the camera of the demo scene through the bunny
+fn bunny_rays(steps: i32) -> impl Iterator<Item = Ray> {
+    (-steps..=steps).flat_map(move |i| {
+        (-steps..=steps).map(move |j| {
+            let direction =
+                Vec3::new(i as f64 * 0.3, j as f64 * 0.3, steps as f64) * (1.0 / steps as f64);
+            Ray::new(Vec3::new(0.0, 0.0, 0.0), direction, 0.0)
+        })
+    })
+}
+
+#[test]
+fn meshes_hit_identically() {
+    let simd = load_bunny().with_kernels(Kernels::detect());
+    let scalar = load_bunny().with_kernels(Kernels::scalar());
+
+    let mut hits = 0;
+    for ray in bunny_rays(50) {
+        let describe = |object: &TriangleMesh| {
+            object.hit(&ray).map(|hit| {
+                let n = hit.normal;
+                [hit.distance, n.x, n.y, n.z].map(f64::to_bits)
+            })
+        };
+        assert_eq!(describe(&simd), describe(&scalar), "{ray:?}");
+        assert_eq!(simd.hit_any(&ray), scalar.hit_any(&ray), "{ray:?}");
+        hits += simd.hit_any(&ray) as usize;
+    }
+    assert!(hits > 250, "Only {hits} rays hit the bunny");
+}
+
+#[test]
+fn hierarchy_finds_closest_hits() {
+    let bunny = load_bunny();
+    let nodes_before = stats::thread_stats().get(Counter::BvhNodes);
+    let mut hits = 0;
+    for ray in bunny_rays(20) {
+        // The intervals are computed from all triangles, without the hierarchy
+        let closest = bunny
+            .intervals(&ray)
+            .first()
+            .map(|interval| interval.enter.distance);
+        let hit = bunny.hit(&ray).map(|hit| hit.distance);
+        assert_eq!(hit, closest, "{ray:?}");
+        assert_eq!(bunny.hit_any(&ray), hit.is_some(), "{ray:?}");
+        hits += hit.is_some() as usize;
+    }
+    assert!(hits > 1000, "Only {hits} rays hit the bunny");
+    assert!(stats::thread_stats().get(Counter::BvhNodes) > nodes_before);
+}
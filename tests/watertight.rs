@@ -0,0 +1,136 @@
+//! Fires dense grids of rays at closed meshes and checks that none of them leaks through the
+//! edges or vertices shared by neighboring triangles.
+
+use std::path::Path;
+
+use rust_raytracer::math3d::Vec3;
+use rust_raytracer::mesh::{MeshData, TriangleMesh};
+use rust_raytracer::ply::parse_ply;
+use rust_raytracer::ray::Ray;
+use rust_raytracer::scene_objects::{Material, Object3D};
+use rust_raytracer::stl::parse_stl;
+
+/// The closed test meshes, all convex and around the origin with a radius of about one
+fn closed_meshes() -> Vec<(&'static str, MeshData)> {
+    let path = |file: &str| {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(file)
+    };
+    vec![
+        ("icosphere", parse_ply(&path("icosphere.ply")).unwrap()),
+        ("cube", parse_stl(&path("cube.stl")).unwrap()),
+        ("octahedron", parse_stl(&path("octahedron.stl")).unwrap()),
+    ]
+}
+
+/// Returns the meshes at their original size and at the scale and position of the demo bunny,
+/// where rounding errors are much larger in absolute terms.
+fn test_meshes() -> Vec<(String, TriangleMesh, Vec3, f64)> {
+    let mut meshes = Vec::new();
+    for (name, data) in closed_meshes() {
+        for (center, scale) in [
+            (Vec3::new(0.0, 0.0, 0.0), 1.0),
+            (Vec3::new(30.0, 500.0, 1500.0), 10000.0),
+        ] {
+            let data = MeshData {
+                positions: data.positions.iter().map(|p| p * scale + center).collect(),
+                normals: None,
+                colors: None,
+                texcoords: None,
+                triangles: data.triangles.clone(),
+                materials: None,
+            };
+            let mesh = TriangleMesh::new(data, Material::new_diffuse(Vec3::new(1.0, 1.0, 1.0)));
+            meshes.push((format!("{name} x{scale}"), mesh, center, scale));
+        }
+    }
+    meshes
+}
+
+#[test]
+fn rays_from_inside_always_hit() {
+    const STEPS: usize = 64;
+    for (name, mesh, center, scale) in test_meshes() {
+        for offset in [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.1, -0.2, 0.05),
+            Vec3::new(-0.3, 0.1, 0.2),
+        ] {
+            let origin = center + offset * scale;
+            // Directions on a grid over the cube around the origin, which includes the
+            // directions towards the vertices and edges of the cube and the octahedron
+            for face in 0..6 {
+                for i in 0..=STEPS {
+                    for j in 0..=STEPS {
+                        let a = i as f64 / STEPS as f64 * 2.0 - 1.0;
+                        let b = j as f64 / STEPS as f64 * 2.0 - 1.0;
+                        let sign = if face % 2 == 0 { 1.0 } else { -1.0 };
+                        let d = match face / 2 {
+                            0 => Vec3::new(sign, a, b),
+                            1 => Vec3::new(a, sign, b),
+                            _ => Vec3::new(a, b, sign),
+                        };
+                        let d = d.normalized();
+                        assert!(
+                            mesh.hit(&Ray::new(origin, d, 0.0)).is_some(),
+                            "{name}: ray from {origin} towards {d} leaks"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn parallel_rays_enter_and_leave_once() {
+    const STEPS: usize = 64;
+    for (name, mesh, center, scale) in test_meshes() {
+        for d in [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0).normalized(),
+        ] {
+            let (u, v) = d.perpendicular_axes();
+            // A grid around the center within the silhouette of every mesh, through the edges
+            // and vertices on the axes and diagonals
+            for i in 0..=STEPS {
+                for j in 0..=STEPS {
+                    let a = (i as f64 / STEPS as f64 - 0.5) * 0.8;
+                    let b = (j as f64 / STEPS as f64 - 0.5) * 0.8;
+                    let origin = center + (u * a + v * b - d * 5.0) * scale;
+                    assert!(
+                        mesh.hit(&Ray::new(origin, d, 0.0)).is_some(),
+                        "{name}: ray from {origin} along {d} leaks"
+                    );
+                    // The line is the same from behind the mesh, with negative distances
+                    let behind = origin + d * (10.0 * scale);
+                    for origin in [origin, behind] {
+                        assert_eq!(
+                            mesh.intervals(&Ray::new(origin, d, 0.0)).len(),
+                            1,
+                            "{name}: ray from {origin} along {d} does not pass through once"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn open_meshes_have_no_intervals() {
+    let triangle = [
+        Vec3::new(-1.0, -1.0, 0.0),
+        Vec3::new(1.0, -1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ];
+    let data = MeshData::from_triangle_soup(&[triangle]).unwrap();
+    let mesh = TriangleMesh::new(data, Material::new_diffuse(Vec3::new(1.0, 1.0, 1.0)));
+    let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+    assert!(mesh.hit(&ray).is_some());
+    // The line crosses the surface once, so it is not clear which part is inside
+    assert!(mesh.intervals(&ray).is_empty());
+}